@@ -0,0 +1,24 @@
+use ast::*;
+use typeck::TypeCheck;
+
+// Value of an expression made of literals and casts, None if it depends on anything else, did
+// not type check or traps. Operators wrap at the type the checker gave them like they do when the
+// program runs, so a condition is only called constant when it evaluates the same way there.
+pub(crate) fn constant(expression: &Expression, types: &TypeCheck) -> Option<i128> {
+    match expression {
+        Expression::LiteralExpression(literal, _) => literal.value().parse().ok(),
+        Expression::BinOpExpression(bin_op) => {
+            let left = constant(&bin_op.left, types)?;
+            let right = constant(&bin_op.right, types)?;
+            match types.type_of(expression)? {
+                Type::SimpleType(SimpleType::Void) => None,
+                Type::SimpleType(datatype) => bin_op.op.evaluate(datatype, left, right).ok(),
+            }
+        }
+        Expression::CastExpression(cast) => match cast.datatype {
+            Type::SimpleType(SimpleType::Void) => None,
+            Type::SimpleType(datatype) => Some(datatype.wrap(constant(&cast.value, types)?)),
+        },
+        _ => None,
+    }
+}
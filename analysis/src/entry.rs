@@ -0,0 +1,159 @@
+use ast::*;
+
+// A program starts at `fn main`, which either takes no parameters or a single integer parameter
+// that receives the number of command line arguments, like argc in C. There are no pointers to
+// pass argv with. main returns void, which exits with status 0, or an integer whose value
+// becomes the exit status, see exit_status.
+pub struct EntryCheck {
+    entry: Option<EntryPoint>,
+    errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryPoint {
+    pub id: NodeId,
+    // Type of the argument count, None if main takes no parameters
+    pub argc: Option<Type>,
+    pub return_type: Type,
+}
+
+impl EntryPoint {
+    // Status the process exits with when main returns value, None for a void main. The value
+    // is converted to an i32 like an `as` cast, the operating system may keep fewer bits
+    pub fn exit_status(&self, value: Option<i128>) -> i32 {
+        match value {
+            Some(value) if self.return_type != Type::SimpleType(SimpleType::Void) => {
+                SimpleType::I32.wrap(value) as i32
+            }
+            _ => 0,
+        }
+    }
+}
+
+impl EntryCheck {
+    pub fn new(program: &Program) -> Self {
+        let mut check = EntryCheck {
+            entry: None,
+            errors: Vec::new(),
+        };
+        let main = program.declarations.iter().find_map(|declaration| {
+            let Declaration::FunctionDeclaration(function) = declaration;
+            (function.name == "main").then_some(function)
+        });
+        match main {
+            Some(main) => check.main(main),
+            None => check.errors.push(String::from("No main function found")),
+        }
+        check
+    }
+
+    pub fn get_errors(&self) -> &Vec<String> {
+        &self.errors
+    }
+
+    // Only set when main has an allowed signature
+    pub fn entry(&self) -> Option<&EntryPoint> {
+        self.entry.as_ref()
+    }
+
+    fn main(&mut self, main: &FunctionDeclaration) {
+        let argc = match main.parameters.as_slice() {
+            [] => None,
+            [argc] if argc.datatype != Type::SimpleType(SimpleType::Void) => Some(argc.datatype),
+            [argc] => {
+                self.errors.push(format!(
+                    "Parameter \"{}\" of \"main\" at {} has type void, the argument count must be an integer",
+                    argc.name, argc.meta.span
+                ));
+                return;
+            }
+            parameters => {
+                self.errors.push(format!(
+                    "Function \"main\" at {} takes {} parameters, expected none or an integer argument count",
+                    main.meta.span,
+                    parameters.len()
+                ));
+                return;
+            }
+        };
+        // Every return type is either void or an integer, so all of them are allowed
+        self.entry = Some(EntryPoint {
+            id: main.meta.id,
+            argc,
+            return_type: main.return_type,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ast::build::*;
+
+    fn errors(functions: Vec<FunctionDeclaration>) -> Vec<String> {
+        EntryCheck::new(&program(functions)).get_errors().clone()
+    }
+
+    #[test]
+    fn allowed_signatures() {
+        for main in [
+            fn_decl("main").body([]),
+            fn_decl("main").returns(i32()).body([ret(lit(0))]),
+            fn_decl("main")
+                .param("argc", u64())
+                .returns(u8())
+                .body([ret(lit(0))]),
+        ] {
+            assert_eq!(errors(vec![main]), Vec::<String>::new());
+        }
+
+        let check = EntryCheck::new(&program([
+            fn_decl("f").body([]),
+            fn_decl("main").param("argc", i32()).returns(u64()).body([]),
+        ]));
+        let entry = check.entry().unwrap();
+        assert_eq!(entry.argc, Some(i32()));
+        assert_eq!(entry.return_type, u64());
+        assert_eq!(entry.id, NodeId(2));
+    }
+
+    #[test]
+    fn rejected_signatures() {
+        assert_eq!(
+            errors(vec![fn_decl("f").body([])]),
+            vec![String::from("No main function found")]
+        );
+        assert_eq!(
+            errors(vec![
+                fn_decl("main")
+                    .param("argc", i32())
+                    .param("argv", u64())
+                    .body([])
+            ]),
+            vec![String::from(
+                "Function \"main\" at 0:0 takes 2 parameters, expected none or an integer argument count"
+            )]
+        );
+        assert_eq!(
+            errors(vec![fn_decl("main").param("argc", void()).body([])]),
+            vec![String::from(
+                "Parameter \"argc\" of \"main\" at 0:0 has type void, the argument count must be an integer"
+            )]
+        );
+    }
+
+    #[test]
+    fn exit_status() {
+        let entry = |return_type| EntryPoint {
+            id: NodeId(1),
+            argc: None,
+            return_type,
+        };
+        assert_eq!(entry(void()).exit_status(None), 0);
+        assert_eq!(entry(u8()).exit_status(Some(42)), 42);
+        assert_eq!(entry(i8()).exit_status(Some(-1)), -1);
+        // Wider values keep their low 32 bits
+        assert_eq!(entry(u64()).exit_status(Some(0x1_0000_0007)), 7);
+        assert_eq!(entry(u64()).exit_status(Some(u64::MAX as i128)), -1);
+    }
+}
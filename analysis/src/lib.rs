@@ -0,0 +1,6 @@
+// Checks over whole programs that run after type checking
+mod constant;
+pub mod entry;
+pub mod lints;
+pub mod returns;
+mod unreachable;
@@ -0,0 +1,350 @@
+use std::collections::{HashMap, HashSet};
+
+use ast::*;
+use resolve::Resolution;
+
+use crate::unreachable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    // A let that is never read or assigned
+    UnusedVariable,
+    // A parameter that is never read or assigned
+    UnusedParameter,
+    // A let or parameter that is assigned but whose value is never read
+    UnusedAssignment,
+    // A function that cannot be reached through calls starting at main
+    DeadFunction,
+    // A statement that can never run
+    UnreachableCode,
+    // An if or while condition made of constants only
+    ConstantCondition,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 6] = [
+        Lint::UnusedVariable,
+        Lint::UnusedParameter,
+        Lint::UnusedAssignment,
+        Lint::DeadFunction,
+        Lint::UnreachableCode,
+        Lint::ConstantCondition,
+    ];
+
+    // Name used to silence the lint, shown after each warning
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnusedParameter => "unused-parameter",
+            Lint::UnusedAssignment => "unused-assignment",
+            Lint::DeadFunction => "dead-function",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::ConstantCondition => "constant-condition",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+}
+
+// Warnings about code that has no effect or never runs. Lints in `allowed` are not reported, and variables
+// and parameters whose name starts with an underscore are never reported as unused.
+pub struct Lints {
+    warnings: Vec<String>,
+}
+
+impl Lints {
+    pub fn new(program: &Program, allowed: &[Lint]) -> Self {
+        let mut usage = Usage {
+            resolution: Resolution::new(program),
+            variables: Vec::new(),
+            reads: HashSet::new(),
+            writes: HashSet::new(),
+            functions: Vec::new(),
+            calls: HashMap::new(),
+        };
+        for declaration in &program.declarations {
+            let Declaration::FunctionDeclaration(function) = declaration;
+            usage.function(function);
+        }
+
+        let mut lints = Lints {
+            warnings: Vec::new(),
+        };
+        let mut warn = |lint: Lint, message: String| {
+            if !allowed.contains(&lint) {
+                lints
+                    .warnings
+                    .push(format!("{} [{}]", message, lint.name()));
+            }
+        };
+
+        for variable in &usage.variables {
+            if variable.name.starts_with('_') || usage.reads.contains(&variable.id) {
+                continue;
+            }
+            let assigned = usage.writes.contains(&variable.id);
+            match (variable.parameter, assigned) {
+                (_, true) => warn(
+                    Lint::UnusedAssignment,
+                    format!(
+                        "Variable \"{}\" at {} is assigned but never read",
+                        variable.name, variable.span
+                    ),
+                ),
+                (true, false) => warn(
+                    Lint::UnusedParameter,
+                    format!(
+                        "Unused parameter \"{}\" at {} in function \"{}\"",
+                        variable.name, variable.span, variable.function
+                    ),
+                ),
+                (false, false) => warn(
+                    Lint::UnusedVariable,
+                    format!("Unused variable \"{}\" at {}", variable.name, variable.span),
+                ),
+            }
+        }
+
+        // Without a main there is nothing to be reachable from
+        if let Some(main) = usage.functions.iter().find(|(_, name, _)| name == "main") {
+            let mut reachable = HashSet::from([main.0]);
+            let mut stack = vec![main.0];
+            while let Some(function) = stack.pop() {
+                for callee in usage.calls.get(&function).into_iter().flatten() {
+                    if reachable.insert(*callee) {
+                        stack.push(*callee);
+                    }
+                }
+            }
+            for (id, name, span) in &usage.functions {
+                if !reachable.contains(id) {
+                    warn(
+                        Lint::DeadFunction,
+                        format!(
+                            "Function \"{}\" at {} is never called from main",
+                            name, span
+                        ),
+                    );
+                }
+            }
+        }
+
+        unreachable::check(program, &mut warn);
+
+        lints
+    }
+
+    pub fn get_warnings(&self) -> &Vec<String> {
+        &self.warnings
+    }
+}
+
+struct Variable {
+    id: NodeId,
+    name: String,
+    span: Span,
+    parameter: bool,
+    function: String,
+}
+
+// Reads and writes of every variable and the calls made by every function
+struct Usage {
+    resolution: Resolution,
+    // In source order
+    variables: Vec<Variable>,
+    reads: HashSet<NodeId>,
+    writes: HashSet<NodeId>,
+    // In source order
+    functions: Vec<(NodeId, String, Span)>,
+    // caller -> callees
+    calls: HashMap<NodeId, Vec<NodeId>>,
+}
+
+impl Usage {
+    fn function(&mut self, function: &FunctionDeclaration) {
+        // A duplicate function was not defined by the resolver and is not linted
+        if self.resolution.definition(function.meta.id).is_none() {
+            return;
+        }
+        self.functions
+            .push((function.meta.id, function.name.clone(), function.meta.span));
+        for parameter in &function.parameters {
+            self.variable(function, &parameter.name, &parameter.meta, true);
+        }
+        self.body(function, &function.body);
+    }
+
+    fn variable(
+        &mut self,
+        function: &FunctionDeclaration,
+        name: &str,
+        meta: &Meta,
+        parameter: bool,
+    ) {
+        // Duplicate parameters were not defined by the resolver either
+        if self.resolution.definition(meta.id).is_none() {
+            return;
+        }
+        self.variables.push(Variable {
+            id: meta.id,
+            name: name.to_string(),
+            span: meta.span,
+            parameter,
+            function: function.name.clone(),
+        });
+    }
+
+    fn body(&mut self, function: &FunctionDeclaration, body: &[Statement]) {
+        for statement in body {
+            match statement {
+                Statement::DeclarationStatement(declaration) => {
+                    self.expression(function, &declaration.value);
+                    self.variable(function, &declaration.variable, &declaration.meta, false);
+                }
+                Statement::VariableAssignmentStatement(assignment) => {
+                    self.expression(function, &assignment.value);
+                    if let Some(variable) = self.resolution.resolve(assignment.meta.id) {
+                        self.writes.insert(variable.id);
+                    }
+                }
+                Statement::IfStatement(if_statement) => {
+                    let mut next = Some(if_statement);
+                    while let Some(if_statement) = next {
+                        if let Some(condition) = &if_statement.condition {
+                            self.expression(function, condition);
+                        }
+                        self.body(function, &if_statement.body);
+                        next = if_statement.tail_conditions.as_deref();
+                    }
+                }
+                Statement::WhileStatement(while_statement) => {
+                    self.expression(function, &while_statement.condition);
+                    self.body(function, &while_statement.body);
+                }
+                Statement::ReturnStatement(value, _) => {
+                    if let Some(value) = value {
+                        self.expression(function, value);
+                    }
+                }
+                Statement::Expression(expression) => self.expression(function, expression),
+            }
+        }
+    }
+
+    fn expression(&mut self, function: &FunctionDeclaration, expression: &Expression) {
+        match expression {
+            Expression::BinOpExpression(bin_op) => {
+                self.expression(function, &bin_op.left);
+                self.expression(function, &bin_op.right);
+            }
+            Expression::LiteralExpression(..) => {}
+            Expression::VariableReferenceExpression(_, meta) => {
+                if let Some(variable) = self.resolution.resolve(meta.id) {
+                    self.reads.insert(variable.id);
+                }
+            }
+            Expression::FunctionCallExpression(call) => {
+                if let Some(callee) = self.resolution.resolve(call.meta.id) {
+                    self.calls
+                        .entry(function.meta.id)
+                        .or_default()
+                        .push(callee.id);
+                }
+                for parameter in &call.parameters {
+                    self.expression(function, parameter);
+                }
+            }
+            Expression::CastExpression(cast) => self.expression(function, &cast.value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn warnings(source: &str, allowed: &[Lint]) -> Vec<String> {
+        let parser = Parser::new(Lexer::tokenise_source(source));
+        assert_eq!(parser.get_errors(), &Vec::<String>::new());
+        Lints::new(parser.get_program().unwrap(), allowed)
+            .get_warnings()
+            .clone()
+    }
+
+    const SOURCE: &str = "fn main(argc: u64, _argv: u64) -> u64 {\n\
+                          let a: u64 = 1;\n\
+                          let b: u64 = 2;\n\
+                          let c: u64 = 3;\n\
+                          let _d: u64 = 4;\n\
+                          c = helper(b);\n\
+                          return 0;\n\
+                          }\n\
+                          fn helper(x: u64) -> u64 { x = 1; return 2; }\n\
+                          fn unused() -> void {}\n\
+                          fn also_unused() -> void { unused(); }";
+
+    #[test]
+    fn reports_every_lint() {
+        assert_eq!(
+            warnings(SOURCE, &[]),
+            vec![
+                String::from(
+                    "Unused parameter \"argc\" at 0:8 in function \"main\" [unused-parameter]"
+                ),
+                String::from("Unused variable \"a\" at 1:4 [unused-variable]"),
+                String::from(
+                    "Variable \"c\" at 3:4 is assigned but never read [unused-assignment]"
+                ),
+                String::from(
+                    "Variable \"x\" at 8:10 is assigned but never read [unused-assignment]"
+                ),
+                String::from(
+                    "Function \"unused\" at 9:3 is never called from main [dead-function]"
+                ),
+                String::from(
+                    "Function \"also_unused\" at 10:3 is never called from main [dead-function]"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn allowed_lints_are_silent() {
+        assert_eq!(
+            warnings(
+                SOURCE,
+                &[
+                    Lint::UnusedParameter,
+                    Lint::UnusedAssignment,
+                    Lint::DeadFunction
+                ]
+            ),
+            vec![String::from(
+                "Unused variable \"a\" at 1:4 [unused-variable]"
+            )]
+        );
+        assert_eq!(warnings(SOURCE, &Lint::ALL), Vec::<String>::new());
+        assert_eq!(Lint::from_name("dead-function"), Some(Lint::DeadFunction));
+        assert_eq!(Lint::from_name("dead_function"), None);
+    }
+
+    #[test]
+    fn recursion_is_not_a_use_from_main() {
+        assert_eq!(
+            warnings(
+                "fn main() -> void { a(); }\n\
+                 fn a() -> void { b(); }\n\
+                 fn b() -> void { a(); }\n\
+                 fn c() -> void { c(); }",
+                &[]
+            ),
+            vec![String::from(
+                "Function \"c\" at 3:3 is never called from main [dead-function]"
+            )]
+        );
+    }
+}
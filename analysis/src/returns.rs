@@ -0,0 +1,184 @@
+use ast::*;
+use typeck::TypeCheck;
+
+use crate::constant::constant;
+
+// Every path through a non-void function has to end in `return value;`, and a void function may
+// only use a bare `return;`. A statement always returns when it is a return, or an if chain that
+// ends in an else and whose branches all return. A while whose condition is a nonzero constant
+// never ends, other loops are assumed to possibly not run, so a return inside them does not count
+// for the code after it.
+pub struct ReturnCheck {
+    errors: Vec<String>,
+}
+
+impl ReturnCheck {
+    pub fn new(program: &Program) -> Self {
+        let mut check = ReturnCheck { errors: Vec::new() };
+        let types = TypeCheck::new(program);
+        for declaration in &program.declarations {
+            let Declaration::FunctionDeclaration(function) = declaration;
+            check.function(function, &types);
+        }
+        check
+    }
+
+    pub fn get_errors(&self) -> &Vec<String> {
+        &self.errors
+    }
+
+    fn function(&mut self, function: &FunctionDeclaration, types: &TypeCheck) {
+        self.return_values(function, &function.body);
+        if function.return_type != Type::SimpleType(SimpleType::Void)
+            && !always_returns(&function.body, types)
+        {
+            self.errors.push(format!(
+                "Function \"{}\" at {} can reach its end without returning a {}",
+                function.name, function.meta.span, function.return_type
+            ));
+        }
+    }
+
+    fn return_values(&mut self, function: &FunctionDeclaration, body: &[Statement]) {
+        let void = function.return_type == Type::SimpleType(SimpleType::Void);
+        for statement in body {
+            match statement {
+                Statement::ReturnStatement(Some(_), meta) if void => {
+                    self.errors.push(format!(
+                        "Return with a value at {} in function \"{}\" returning void",
+                        meta.span, function.name
+                    ));
+                }
+                Statement::ReturnStatement(None, meta) if !void => {
+                    self.errors.push(format!(
+                        "Return without a value at {} in function \"{}\" returning {}",
+                        meta.span, function.name, function.return_type
+                    ));
+                }
+                Statement::IfStatement(if_statement) => {
+                    let mut next = Some(if_statement);
+                    while let Some(if_statement) = next {
+                        self.return_values(function, &if_statement.body);
+                        next = if_statement.tail_conditions.as_deref();
+                    }
+                }
+                Statement::WhileStatement(while_statement) => {
+                    self.return_values(function, &while_statement.body)
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// Control never reaches the end of the body
+pub fn always_returns(body: &[Statement], types: &TypeCheck) -> bool {
+    body.iter().any(|statement| diverges(statement, types))
+}
+
+// Control never reaches the statement after this one
+pub fn diverges(statement: &Statement, types: &TypeCheck) -> bool {
+    match statement {
+        Statement::ReturnStatement(..) => true,
+        Statement::IfStatement(if_statement) => {
+            let mut next = Some(if_statement);
+            while let Some(if_statement) = next {
+                if !always_returns(&if_statement.body, types) {
+                    return false;
+                }
+                // An if or else if without an else can be skipped entirely
+                if if_statement.condition.is_some() && if_statement.tail_conditions.is_none() {
+                    return false;
+                }
+                next = if_statement.tail_conditions.as_deref();
+            }
+            true
+        }
+        Statement::WhileStatement(while_statement) => {
+            matches!(constant(&while_statement.condition, types), Some(value) if value != 0)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lints::{Lint, Lints};
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn errors(source: &str) -> Vec<String> {
+        let parser = Parser::new(Lexer::tokenise_source(source));
+        assert_eq!(parser.get_errors(), &Vec::<String>::new());
+        ReturnCheck::new(parser.get_program().unwrap())
+            .get_errors()
+            .clone()
+    }
+
+    #[test]
+    fn every_path_returns() {
+        assert_eq!(
+            errors(
+                "fn a(x: u64) -> u64 { return x; }\n\
+                 fn b(x: u64) -> u64 { if x { return 1; } else if x - 1 { return 2; } else { return 3; } }\n\
+                 fn c(x: u64) -> u64 { if x { x = 1; } else { x = 2; } while x { x = x - 1; } return x; }\n\
+                 fn d(x: u64) -> u64 { if x { if x { return 1; } else { return 2; } } else { return 3; } }\n\
+                 fn e(x: u64) -> void { if x { return; } }\n\
+                 fn f() -> void {}"
+            ),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn paths_falling_off_the_end() {
+        assert_eq!(
+            errors(
+                "fn main(a: u64) -> u64 {}\n\
+                 fn b(x: u64) -> u8 { if x { return 1; } }\n\
+                 fn c(x: u64) -> u8 { if x { return 1; } else if x - 1 { return 2; } }\n\
+                 fn d(x: u64) -> u8 { if x { return 1; } else if x - 1 { x = 2; } else { return 3; } }\n\
+                 fn e(x: u64) -> u8 { while x { return 1; } }"
+            ),
+            vec![
+                String::from("Function \"main\" at 0:3 can reach its end without returning a u64"),
+                String::from("Function \"b\" at 1:3 can reach its end without returning a u8"),
+                String::from("Function \"c\" at 2:3 can reach its end without returning a u8"),
+                String::from("Function \"d\" at 3:3 can reach its end without returning a u8"),
+                String::from("Function \"e\" at 4:3 can reach its end without returning a u8"),
+            ]
+        );
+    }
+
+    #[test]
+    fn endless_loops_never_reach_the_end() {
+        let source = "fn main() -> u64 { while 1 { print_int(1); } }\n\
+                      fn f(x: u8) -> u8 { while (0 - 1) as u8 { x = x + 1; } }";
+        assert_eq!(errors(source), Vec::<String>::new());
+
+        // Nothing else complains about it either
+        let parser = Parser::new(Lexer::tokenise_source(source));
+        let program = parser.get_program().unwrap();
+        assert_eq!(TypeCheck::new(program).get_errors(), &Vec::<String>::new());
+        assert_eq!(
+            Lints::new(program, &[Lint::DeadFunction]).get_warnings(),
+            &Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn return_values_match_the_return_type() {
+        assert_eq!(
+            errors(
+                "fn f(x: u64) -> void { if x { return x; } while x { return 1; } }\n\
+                 fn g(x: u64) -> u64 { if x { return; } return x; }"
+            ),
+            vec![
+                String::from("Return with a value at 0:30 in function \"f\" returning void"),
+                String::from("Return with a value at 0:52 in function \"f\" returning void"),
+                String::from("Return without a value at 1:29 in function \"g\" returning u64"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use ast::*;
+
+use typeck::TypeCheck;
+
+use crate::{constant::constant, lints::Lint, returns::diverges};
+
+// Finds statements that can never run: anything after a statement that always returns or after
+// a `while` whose condition is a nonzero constant, and the branches behind a constant condition.
+// `while 1` is the usual way to write an endless loop, so only a condition that is always false
+// is reported for loops.
+pub(crate) fn check(program: &Program, warn: &mut impl FnMut(Lint, String)) {
+    let types = TypeCheck::new(program);
+    for declaration in &program.declarations {
+        let Declaration::FunctionDeclaration(function) = declaration;
+        body(&function.body, &types, warn);
+    }
+}
+
+fn body(body: &[Statement], types: &TypeCheck, warn: &mut impl FnMut(Lint, String)) {
+    let mut reported = false;
+    for (i, statement) in body.iter().enumerate() {
+        match statement {
+            Statement::IfStatement(if_statement) => if_chain(if_statement, types, warn),
+            Statement::WhileStatement(while_statement) => {
+                if constant(&while_statement.condition, types) == Some(0) {
+                    warn(
+                        Lint::ConstantCondition,
+                        always_false(&while_statement.condition, &while_statement.body),
+                    );
+                }
+                self::body(&while_statement.body, types, warn);
+            }
+            _ => {}
+        }
+
+        if !reported && diverges(statement, types) {
+            if let Some(dead) = body.get(i + 1) {
+                warn(
+                    Lint::UnreachableCode,
+                    format!("Unreachable statement at {}", dead.meta().span),
+                );
+            }
+            reported = true;
+        }
+    }
+}
+
+fn if_chain(if_statement: &IfStatement, types: &TypeCheck, warn: &mut impl FnMut(Lint, String)) {
+    let mut next = Some(if_statement);
+    while let Some(if_statement) = next {
+        if let Some(condition) = &if_statement.condition {
+            match constant(condition, types) {
+                Some(0) => warn(
+                    Lint::ConstantCondition,
+                    always_false(condition, &if_statement.body),
+                ),
+                Some(_) => warn(
+                    Lint::ConstantCondition,
+                    match &if_statement.tail_conditions {
+                        Some(tail) => format!(
+                            "Condition at {} is always true, unreachable branch at {}",
+                            condition.meta().span,
+                            tail.meta.span
+                        ),
+                        None => format!("Condition at {} is always true", condition.meta().span),
+                    },
+                ),
+                None => {}
+            }
+        }
+        body(&if_statement.body, types, warn);
+        next = if_statement.tail_conditions.as_deref();
+    }
+}
+
+fn always_false(condition: &Expression, body: &[Statement]) -> String {
+    match body.first() {
+        Some(dead) => format!(
+            "Condition at {} is always false, unreachable statement at {}",
+            condition.meta().span,
+            dead.meta().span
+        ),
+        None => format!("Condition at {} is always false", condition.meta().span),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lints::{Lint, Lints};
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn warnings(source: &str) -> Vec<String> {
+        let parser = Parser::new(Lexer::tokenise_source(source));
+        assert_eq!(parser.get_errors(), &Vec::<String>::new());
+        let allowed: Vec<Lint> = Lint::ALL
+            .into_iter()
+            .filter(|lint| ![Lint::UnreachableCode, Lint::ConstantCondition].contains(lint))
+            .collect();
+        Lints::new(parser.get_program().unwrap(), &allowed)
+            .get_warnings()
+            .clone()
+    }
+
+    #[test]
+    fn statements_after_return() {
+        assert_eq!(
+            warnings(
+                "fn f(a: u64) -> u64 {\n\
+                 if a { return 1; a = 2; a = 3; }\n\
+                 if a { return 1; } else { return 2; }\n\
+                 a = 4;\n\
+                 return a;\n\
+                 }\n\
+                 fn g(a: u64) -> u64 { while 1 { a = a + 1; } return a; }\n\
+                 fn h(a: u64) -> u64 { if a { return 1; } return a; }"
+            ),
+            vec![
+                String::from("Unreachable statement at 1:17 [unreachable-code]"),
+                String::from("Unreachable statement at 3:0 [unreachable-code]"),
+                String::from("Unreachable statement at 6:45 [unreachable-code]"),
+            ]
+        );
+    }
+
+    #[test]
+    fn constant_conditions() {
+        assert_eq!(
+            warnings(
+                "fn f(a: u64) -> void {\n\
+                 while 0 { a = 1; }\n\
+                 while 2 - 2 {}\n\
+                 if 1 + 1 { a = 2; } else if a { a = 3; }\n\
+                 if a { a = 4; } else if 256 as u8 { a = 5; } else {}\n\
+                 if 3 { a = 6; }\n\
+                 if (0 - 128) as i8 + (0 - 128) as i8 { a = 7; }\n\
+                 }"
+            ),
+            vec![
+                String::from(
+                    "Condition at 1:6 is always false, unreachable statement at 1:10 [constant-condition]"
+                ),
+                String::from("Condition at 2:8 is always false [constant-condition]"),
+                String::from(
+                    "Condition at 3:5 is always true, unreachable branch at 3:25 [constant-condition]"
+                ),
+                String::from(
+                    "Condition at 4:28 is always false, unreachable statement at 4:36 [constant-condition]"
+                ),
+                String::from("Condition at 5:3 is always true [constant-condition]"),
+                String::from(
+                    "Condition at 6:19 is always false, unreachable statement at 6:39 [constant-condition]"
+                ),
+            ]
+        );
+    }
+}
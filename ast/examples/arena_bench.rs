@@ -0,0 +1,222 @@
+// Compares the boxed AST with the arena layout on a large generated program, both the time to
+// walk them and the memory they take:
+//
+//     cargo run --release -p ast --example arena_bench [functions]
+
+use std::{env, hint::black_box, time::Instant};
+
+use ast::{
+    arena::{ArenaExpression, ArenaProgram, ArenaStatement, ExprId, StmtId, boxed_heap_bytes},
+    build::*,
+    *,
+};
+use intern::Interner;
+
+const STATEMENTS_PER_FUNCTION: usize = 50;
+
+fn generate(functions: usize) -> Program {
+    program((0..functions).map(|f| {
+        let mut body = Vec::new();
+        for s in 0..STATEMENTS_PER_FUNCTION {
+            let name = format!("v{}", s % 16);
+            let value = add(
+                mul(var(&name), lit(s)),
+                sub(
+                    call(&format!("f{}", (f + 1) % functions), [var("a"), lit(f)]),
+                    var("a"),
+                ),
+            );
+            body.push(match s % 4 {
+                0 => decl(&name, u64(), value),
+                1 => assign(&name, value),
+                2 => while_loop(var(&name), [assign(&name, sub(var(&name), lit(1)))]),
+                _ => if_(value, [expr(var(&name))]).else_([ret(var("a"))]),
+            });
+        }
+        body.push(ret(var("a")));
+        fn_decl(&format!("f{}", f))
+            .param("a", u64())
+            .returns(u64())
+            .body(body)
+    }))
+}
+
+fn count_boxed_expression(expression: &Expression) -> usize {
+    match expression {
+        Expression::BinOpExpression(bin_op) => {
+            1 + count_boxed_expression(&bin_op.left) + count_boxed_expression(&bin_op.right)
+        }
+        Expression::LiteralExpression(..) => 1,
+        Expression::VariableReferenceExpression(name, _) => 1 + name.len(),
+        Expression::FunctionCallExpression(call) => {
+            1 + call.name.len()
+                + call
+                    .parameters
+                    .iter()
+                    .map(count_boxed_expression)
+                    .sum::<usize>()
+        }
+        Expression::CastExpression(cast) => 1 + count_boxed_expression(&cast.value),
+    }
+}
+
+fn count_boxed_body(body: &[Statement]) -> usize {
+    body.iter()
+        .map(|statement| match statement {
+            Statement::DeclarationStatement(declaration) => {
+                1 + count_boxed_expression(&declaration.value)
+            }
+            Statement::VariableAssignmentStatement(assignment) => {
+                1 + count_boxed_expression(&assignment.value)
+            }
+            Statement::IfStatement(if_statement) => {
+                let mut count = 0;
+                let mut next = Some(if_statement);
+                while let Some(if_statement) = next {
+                    count += 1 + count_boxed_body(&if_statement.body);
+                    count += if_statement
+                        .condition
+                        .as_ref()
+                        .map_or(0, count_boxed_expression);
+                    next = if_statement.tail_conditions.as_deref();
+                }
+                count
+            }
+            Statement::WhileStatement(while_statement) => {
+                1 + count_boxed_expression(&while_statement.condition)
+                    + count_boxed_body(&while_statement.body)
+            }
+            Statement::ReturnStatement(value, _) => {
+                1 + value.as_ref().map_or(0, count_boxed_expression)
+            }
+            Statement::Expression(expression) => count_boxed_expression(expression),
+        })
+        .sum()
+}
+
+fn count_boxed(program: &Program) -> usize {
+    program
+        .declarations
+        .iter()
+        .map(|declaration| match declaration {
+            Declaration::FunctionDeclaration(function) => count_boxed_body(&function.body),
+        })
+        .sum()
+}
+
+fn count_arena_expression(program: &ArenaProgram, interner: &Interner, id: ExprId) -> usize {
+    match program.arena.expression(id) {
+        ArenaExpression::BinOp { left, right, .. } => {
+            1 + count_arena_expression(program, interner, *left)
+                + count_arena_expression(program, interner, *right)
+        }
+        ArenaExpression::Literal { .. } => 1,
+        ArenaExpression::VariableReference(name) => 1 + interner.resolve(*name).len(),
+        ArenaExpression::FunctionCall { name, parameters } => {
+            1 + interner.resolve(*name).len()
+                + program
+                    .arena
+                    .expressions(*parameters)
+                    .iter()
+                    .map(|parameter| count_arena_expression(program, interner, *parameter))
+                    .sum::<usize>()
+        }
+        ArenaExpression::Cast { value, .. } => {
+            1 + count_arena_expression(program, interner, *value)
+        }
+    }
+}
+
+fn count_arena_statement(program: &ArenaProgram, interner: &Interner, id: StmtId) -> usize {
+    let expression = |id| count_arena_expression(program, interner, id);
+    let body = |list| {
+        program
+            .arena
+            .statements(list)
+            .iter()
+            .map(|id| count_arena_statement(program, interner, *id))
+            .sum::<usize>()
+    };
+    match program.arena.statement(id) {
+        ArenaStatement::Declaration { value, .. } => 1 + expression(*value),
+        ArenaStatement::VariableAssignment { value, .. } => 1 + expression(*value),
+        ArenaStatement::If {
+            condition,
+            body: if_body,
+            tail,
+        } => {
+            1 + condition.map_or(0, expression)
+                + body(*if_body)
+                + tail.map_or(0, |tail| count_arena_statement(program, interner, tail))
+        }
+        ArenaStatement::While {
+            condition,
+            body: while_body,
+        } => 1 + expression(*condition) + body(*while_body),
+        ArenaStatement::Return(value) => 1 + value.map_or(0, expression),
+        ArenaStatement::Expression(value) => expression(*value),
+    }
+}
+
+fn count_arena(program: &ArenaProgram, interner: &Interner) -> usize {
+    program
+        .functions
+        .iter()
+        .flat_map(|function| program.arena.statements(function.body))
+        .map(|id| count_arena_statement(program, interner, *id))
+        .sum()
+}
+
+fn main() {
+    let functions = env::args()
+        .nth(1)
+        .map(|n| n.parse().expect("function count must be a number"))
+        .unwrap_or(2000);
+
+    let start = Instant::now();
+    let boxed = generate(functions);
+    let generate_time = start.elapsed();
+
+    let start = Instant::now();
+    let copy = black_box(boxed.clone());
+    let clone_time = start.elapsed();
+    drop(copy);
+
+    let mut interner = Interner::new();
+    let start = Instant::now();
+    let arena = ArenaProgram::lower(&boxed, &mut interner);
+    let lower_time = start.elapsed();
+
+    let start = Instant::now();
+    let boxed_count = black_box(count_boxed(&boxed));
+    let boxed_walk_time = start.elapsed();
+
+    let start = Instant::now();
+    let arena_count = black_box(count_arena(&arena, &interner));
+    let arena_walk_time = start.elapsed();
+
+    assert_eq!(boxed_count, arena_count);
+
+    println!(
+        "{} functions, {} expressions, {} statements, {} distinct strings",
+        functions,
+        arena.arena.expression_count(),
+        arena.arena.statement_count(),
+        interner.len()
+    );
+    let boxed_bytes = boxed_heap_bytes(&boxed);
+    let arena_bytes = arena.heap_bytes();
+    let interner_bytes = interner.heap_bytes();
+    println!("memory boxed     {:>10} bytes", boxed_bytes);
+    println!(
+        "memory arena     {:>10} bytes ({} nodes and lists, {} interner)",
+        arena_bytes + interner_bytes,
+        arena_bytes,
+        interner_bytes
+    );
+    println!("generate boxed   {:>10.2?}", generate_time);
+    println!("clone boxed      {:>10.2?}", clone_time);
+    println!("lower to arena   {:>10.2?}", lower_time);
+    println!("walk boxed       {:>10.2?}", boxed_walk_time);
+    println!("walk arena       {:>10.2?}", arena_walk_time);
+}
@@ -0,0 +1,622 @@
+// Flat layout of the AST: nodes live in a few vectors and refer to each other by index, names are
+// interned Symbols instead of owned Strings. The Meta of every node is kept in a side table next
+// to it. Lower a Program with `ArenaProgram::lower` and turn it back with `raise`.
+//
+// This is an opt-in representation next to the boxed tree, not a replacement for it: the parser
+// builds a Program and every pass walks that. Only `--emit=ast-stats` and the arena_bench example
+// lower into the arena, to compare the two layouts.
+
+use std::mem::size_of;
+
+use intern::{Interner, Symbol};
+
+use crate::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExprId(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StmtId(u32);
+
+// Contiguous run of ids in Arena::expression_lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExprList {
+    start: u32,
+    len: u32,
+}
+
+// Contiguous run of ids in Arena::statement_lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StmtList {
+    start: u32,
+    len: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArenaExpression {
+    BinOp {
+        left: ExprId,
+        op: Operator,
+        right: ExprId,
+    },
+    Literal {
+        datatype: SimpleType,
+        value: Symbol,
+    },
+    VariableReference(Symbol),
+    FunctionCall {
+        name: Symbol,
+        parameters: ExprList,
+    },
+    Cast {
+        value: ExprId,
+        datatype: Type,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArenaStatement {
+    Declaration {
+        variable: Symbol,
+        datatype: Type,
+        value: ExprId,
+    },
+    VariableAssignment {
+        variable: Symbol,
+        value: ExprId,
+    },
+    // condition is None for the final else, tail is always another If
+    If {
+        condition: Option<ExprId>,
+        body: StmtList,
+        tail: Option<StmtId>,
+    },
+    While {
+        condition: ExprId,
+        body: StmtList,
+    },
+    Return(Option<ExprId>),
+    Expression(ExprId),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArenaParameter {
+    pub name: Symbol,
+    pub datatype: Type,
+    pub meta: Meta,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArenaFunction {
+    pub name: Symbol,
+    pub parameters: Vec<ArenaParameter>,
+    pub return_type: Type,
+    pub body: StmtList,
+    pub meta: Meta,
+}
+
+#[derive(Debug, Default)]
+pub struct Arena {
+    expressions: Vec<ArenaExpression>,
+    // Indexed by ExprId like expressions
+    expression_metas: Vec<Meta>,
+    statements: Vec<ArenaStatement>,
+    // Indexed by StmtId like statements, an expression statement shares the Meta of its expression
+    statement_metas: Vec<Meta>,
+    expression_lists: Vec<ExprId>,
+    statement_lists: Vec<StmtId>,
+}
+
+impl Arena {
+    pub fn expression(&self, id: ExprId) -> &ArenaExpression {
+        &self.expressions[id.0 as usize]
+    }
+
+    pub fn statement(&self, id: StmtId) -> &ArenaStatement {
+        &self.statements[id.0 as usize]
+    }
+
+    pub fn expression_meta(&self, id: ExprId) -> Meta {
+        self.expression_metas[id.0 as usize]
+    }
+
+    pub fn statement_meta(&self, id: StmtId) -> Meta {
+        self.statement_metas[id.0 as usize]
+    }
+
+    pub fn expressions(&self, list: ExprList) -> &[ExprId] {
+        &self.expression_lists[list.start as usize..(list.start + list.len) as usize]
+    }
+
+    pub fn statements(&self, list: StmtList) -> &[StmtId] {
+        &self.statement_lists[list.start as usize..(list.start + list.len) as usize]
+    }
+
+    pub fn expression_count(&self) -> usize {
+        self.expressions.len()
+    }
+
+    pub fn statement_count(&self) -> usize {
+        self.statements.len()
+    }
+
+    // Bytes allocated for the nodes and lists, not counting the interned names
+    pub fn heap_bytes(&self) -> usize {
+        self.expressions.capacity() * size_of::<ArenaExpression>()
+            + self.expression_metas.capacity() * size_of::<Meta>()
+            + self.statements.capacity() * size_of::<ArenaStatement>()
+            + self.statement_metas.capacity() * size_of::<Meta>()
+            + self.expression_lists.capacity() * size_of::<ExprId>()
+            + self.statement_lists.capacity() * size_of::<StmtId>()
+    }
+
+    fn push_expression(&mut self, expression: ArenaExpression, meta: Meta) -> ExprId {
+        self.expressions.push(expression);
+        self.expression_metas.push(meta);
+        ExprId(self.expressions.len() as u32 - 1)
+    }
+
+    fn push_statement(&mut self, statement: ArenaStatement, meta: Meta) -> StmtId {
+        self.statements.push(statement);
+        self.statement_metas.push(meta);
+        StmtId(self.statements.len() as u32 - 1)
+    }
+
+    fn push_expression_list(&mut self, ids: Vec<ExprId>) -> ExprList {
+        let start = self.expression_lists.len() as u32;
+        let len = ids.len() as u32;
+        self.expression_lists.extend(ids);
+        ExprList { start, len }
+    }
+
+    fn push_statement_list(&mut self, ids: Vec<StmtId>) -> StmtList {
+        let start = self.statement_lists.len() as u32;
+        let len = ids.len() as u32;
+        self.statement_lists.extend(ids);
+        StmtList { start, len }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ArenaProgram {
+    pub functions: Vec<ArenaFunction>,
+    pub arena: Arena,
+}
+
+impl ArenaProgram {
+    // Pass the interner taken from the lexer so identifiers keep the symbols it assigned
+    pub fn lower(program: &Program, interner: &mut Interner) -> Self {
+        let mut lowering = Lowering {
+            arena: Arena::default(),
+            interner,
+        };
+        let functions = program
+            .declarations
+            .iter()
+            .map(|declaration| match declaration {
+                Declaration::FunctionDeclaration(function) => lowering.function(function),
+            })
+            .collect();
+        Self {
+            functions,
+            arena: lowering.arena,
+        }
+    }
+
+    // Bytes allocated for the whole program, not counting the interned names
+    pub fn heap_bytes(&self) -> usize {
+        self.functions.capacity() * size_of::<ArenaFunction>()
+            + self
+                .functions
+                .iter()
+                .map(|function| function.parameters.capacity() * size_of::<ArenaParameter>())
+                .sum::<usize>()
+            + self.arena.heap_bytes()
+    }
+
+    pub fn raise(&self, interner: &Interner) -> Program {
+        let raising = Raising {
+            arena: &self.arena,
+            interner,
+        };
+        Program::new(
+            self.functions
+                .iter()
+                .map(|function| Declaration::FunctionDeclaration(raising.function(function)))
+                .collect(),
+        )
+    }
+}
+
+struct Lowering<'a> {
+    arena: Arena,
+    interner: &'a mut Interner,
+}
+
+impl Lowering<'_> {
+    fn function(&mut self, function: &FunctionDeclaration) -> ArenaFunction {
+        ArenaFunction {
+            name: self.interner.intern(&function.name),
+            parameters: function
+                .parameters
+                .iter()
+                .map(|parameter| ArenaParameter {
+                    name: self.interner.intern(&parameter.name),
+                    datatype: parameter.datatype,
+                    meta: parameter.meta,
+                })
+                .collect(),
+            return_type: function.return_type,
+            body: self.body(&function.body),
+            meta: function.meta,
+        }
+    }
+
+    fn body(&mut self, body: &[Statement]) -> StmtList {
+        let ids = body
+            .iter()
+            .map(|statement| self.statement(statement))
+            .collect();
+        self.arena.push_statement_list(ids)
+    }
+
+    fn statement(&mut self, statement: &Statement) -> StmtId {
+        let meta = statement.meta();
+        let statement = match statement {
+            Statement::DeclarationStatement(declaration) => ArenaStatement::Declaration {
+                variable: self.interner.intern(&declaration.variable),
+                datatype: declaration.datatype,
+                value: self.expression(&declaration.value),
+            },
+            Statement::VariableAssignmentStatement(assignment) => {
+                ArenaStatement::VariableAssignment {
+                    variable: self.interner.intern(&assignment.variable),
+                    value: self.expression(&assignment.value),
+                }
+            }
+            Statement::IfStatement(if_statement) => return self.if_statement(if_statement),
+            Statement::WhileStatement(while_statement) => ArenaStatement::While {
+                condition: self.expression(&while_statement.condition),
+                body: self.body(&while_statement.body),
+            },
+            Statement::ReturnStatement(value, _) => {
+                ArenaStatement::Return(value.as_ref().map(|value| self.expression(value)))
+            }
+            Statement::Expression(expression) => {
+                ArenaStatement::Expression(self.expression(expression))
+            }
+        };
+        self.arena.push_statement(statement, *meta)
+    }
+
+    fn if_statement(&mut self, if_statement: &IfStatement) -> StmtId {
+        let condition = if_statement
+            .condition
+            .as_ref()
+            .map(|condition| self.expression(condition));
+        let body = self.body(&if_statement.body);
+        let tail = if_statement
+            .tail_conditions
+            .as_ref()
+            .map(|tail| self.if_statement(tail));
+        self.arena.push_statement(
+            ArenaStatement::If {
+                condition,
+                body,
+                tail,
+            },
+            if_statement.meta,
+        )
+    }
+
+    fn expression(&mut self, expression: &Expression) -> ExprId {
+        let meta = *expression.meta();
+        let expression = match expression {
+            Expression::BinOpExpression(bin_op) => ArenaExpression::BinOp {
+                left: self.expression(&bin_op.left),
+                op: bin_op.op,
+                right: self.expression(&bin_op.right),
+            },
+            Expression::LiteralExpression(literal, _) => ArenaExpression::Literal {
+                datatype: literal.datatype(),
+                value: self.interner.intern(literal.value()),
+            },
+            Expression::VariableReferenceExpression(name, _) => {
+                ArenaExpression::VariableReference(self.interner.intern(name))
+            }
+            Expression::FunctionCallExpression(call) => {
+                let ids = call
+                    .parameters
+                    .iter()
+                    .map(|parameter| self.expression(parameter))
+                    .collect();
+                ArenaExpression::FunctionCall {
+                    name: self.interner.intern(&call.name),
+                    parameters: self.arena.push_expression_list(ids),
+                }
+            }
+            Expression::CastExpression(cast) => ArenaExpression::Cast {
+                value: self.expression(&cast.value),
+                datatype: cast.datatype,
+            },
+        };
+        self.arena.push_expression(expression, meta)
+    }
+}
+
+struct Raising<'a> {
+    arena: &'a Arena,
+    interner: &'a Interner,
+}
+
+impl Raising<'_> {
+    fn name(&self, symbol: Symbol) -> String {
+        self.interner.resolve(symbol).to_string()
+    }
+
+    fn function(&self, function: &ArenaFunction) -> FunctionDeclaration {
+        FunctionDeclaration {
+            name: self.name(function.name),
+            parameters: function
+                .parameters
+                .iter()
+                .map(|parameter| FunctionParameterDeclaration {
+                    name: self.name(parameter.name),
+                    datatype: parameter.datatype,
+                    meta: parameter.meta,
+                })
+                .collect(),
+            return_type: function.return_type,
+            body: self.body(function.body),
+            meta: function.meta,
+        }
+    }
+
+    fn body(&self, body: StmtList) -> Vec<Statement> {
+        self.arena
+            .statements(body)
+            .iter()
+            .map(|id| self.statement(*id))
+            .collect()
+    }
+
+    fn statement(&self, id: StmtId) -> Statement {
+        let meta = self.arena.statement_meta(id);
+        match self.arena.statement(id) {
+            ArenaStatement::Declaration {
+                variable,
+                datatype,
+                value,
+            } => Statement::DeclarationStatement(DeclarationStatement {
+                variable: self.name(*variable),
+                datatype: *datatype,
+                value: self.expression(*value),
+                meta,
+            }),
+            ArenaStatement::VariableAssignment { variable, value } => {
+                Statement::VariableAssignmentStatement(VariableAssignmentStatement {
+                    variable: self.name(*variable),
+                    value: self.expression(*value),
+                    meta,
+                })
+            }
+            ArenaStatement::If { .. } => Statement::IfStatement(self.if_statement(id)),
+            ArenaStatement::While { condition, body } => {
+                Statement::WhileStatement(WhileStatement {
+                    condition: self.expression(*condition),
+                    body: self.body(*body),
+                    meta,
+                })
+            }
+            ArenaStatement::Return(value) => {
+                Statement::ReturnStatement(value.map(|value| self.expression(value)), meta)
+            }
+            ArenaStatement::Expression(expression) => {
+                Statement::Expression(self.expression(*expression))
+            }
+        }
+    }
+
+    fn if_statement(&self, id: StmtId) -> IfStatement {
+        let ArenaStatement::If {
+            condition,
+            body,
+            tail,
+        } = self.arena.statement(id)
+        else {
+            panic!("Tail of an if statement must be another if statement");
+        };
+        IfStatement {
+            condition: condition.map(|condition| self.expression(condition)),
+            body: self.body(*body),
+            tail_conditions: tail.map(|tail| Box::new(self.if_statement(tail))),
+            meta: self.arena.statement_meta(id),
+        }
+    }
+
+    fn expression(&self, id: ExprId) -> Expression {
+        let meta = self.arena.expression_meta(id);
+        match self.arena.expression(id) {
+            ArenaExpression::BinOp { left, op, right } => {
+                Expression::BinOpExpression(BinOpExpression {
+                    left: Box::new(self.expression(*left)),
+                    op: *op,
+                    right: Box::new(self.expression(*right)),
+                    meta,
+                })
+            }
+            ArenaExpression::Literal { datatype, value } => Expression::LiteralExpression(
+                LiteralExpression::new(*datatype, self.name(*value)),
+                meta,
+            ),
+            ArenaExpression::VariableReference(name) => {
+                Expression::VariableReferenceExpression(self.name(*name), meta)
+            }
+            ArenaExpression::FunctionCall { name, parameters } => {
+                Expression::FunctionCallExpression(FunctionCallExpression {
+                    name: self.name(*name),
+                    parameters: self
+                        .arena
+                        .expressions(*parameters)
+                        .iter()
+                        .map(|parameter| self.expression(*parameter))
+                        .collect(),
+                    meta,
+                })
+            }
+            ArenaExpression::Cast { value, datatype } => {
+                Expression::CastExpression(CastExpression {
+                    value: Box::new(self.expression(*value)),
+                    datatype: *datatype,
+                    meta,
+                })
+            }
+        }
+    }
+}
+
+// Bytes the boxed tree allocates for its nodes, lists and names, the counterpart of
+// ArenaProgram::heap_bytes plus the interned names
+pub fn boxed_heap_bytes(program: &Program) -> usize {
+    program.declarations.capacity() * size_of::<Declaration>()
+        + program
+            .declarations
+            .iter()
+            .map(|declaration| match declaration {
+                Declaration::FunctionDeclaration(function) => {
+                    function.name.capacity()
+                        + function.parameters.capacity() * size_of::<FunctionParameterDeclaration>()
+                        + function
+                            .parameters
+                            .iter()
+                            .map(|parameter| parameter.name.capacity())
+                            .sum::<usize>()
+                        + boxed_body_bytes(&function.body)
+                }
+            })
+            .sum::<usize>()
+}
+
+fn boxed_body_bytes(body: &Vec<Statement>) -> usize {
+    body.capacity() * size_of::<Statement>()
+        + body
+            .iter()
+            .map(|statement| match statement {
+                Statement::DeclarationStatement(declaration) => {
+                    declaration.variable.capacity() + boxed_expression_bytes(&declaration.value)
+                }
+                Statement::VariableAssignmentStatement(assignment) => {
+                    assignment.variable.capacity() + boxed_expression_bytes(&assignment.value)
+                }
+                Statement::IfStatement(if_statement) => boxed_if_bytes(if_statement),
+                Statement::WhileStatement(while_statement) => {
+                    boxed_expression_bytes(&while_statement.condition)
+                        + boxed_body_bytes(&while_statement.body)
+                }
+                Statement::ReturnStatement(value, _) => {
+                    value.as_ref().map_or(0, boxed_expression_bytes)
+                }
+                Statement::Expression(expression) => boxed_expression_bytes(expression),
+            })
+            .sum::<usize>()
+}
+
+// Without the IfStatement itself, which lives in a body or in the box of its parent
+fn boxed_if_bytes(if_statement: &IfStatement) -> usize {
+    if_statement
+        .condition
+        .as_ref()
+        .map_or(0, boxed_expression_bytes)
+        + boxed_body_bytes(&if_statement.body)
+        + if_statement
+            .tail_conditions
+            .as_ref()
+            .map_or(0, |tail| size_of::<IfStatement>() + boxed_if_bytes(tail))
+}
+
+// Without the Expression itself, which lives in a box, list or statement of its parent
+fn boxed_expression_bytes(expression: &Expression) -> usize {
+    match expression {
+        Expression::BinOpExpression(bin_op) => {
+            2 * size_of::<Expression>()
+                + boxed_expression_bytes(&bin_op.left)
+                + boxed_expression_bytes(&bin_op.right)
+        }
+        Expression::LiteralExpression(literal, _) => literal.value().len(),
+        Expression::VariableReferenceExpression(name, _) => name.capacity(),
+        Expression::FunctionCallExpression(call) => {
+            call.name.capacity()
+                + call.parameters.capacity() * size_of::<Expression>()
+                + call
+                    .parameters
+                    .iter()
+                    .map(boxed_expression_bytes)
+                    .sum::<usize>()
+        }
+        Expression::CastExpression(cast) => {
+            size_of::<Expression>() + boxed_expression_bytes(&cast.value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::build::*;
+
+    fn sample() -> Program {
+        program([
+            fn_decl("print_int").param("a", u64()).body([]),
+            fn_decl("main").param("a", u64()).returns(u64()).body([
+                decl("b", u8(), typed_lit(SimpleType::U8, 3)),
+                while_loop(var("b"), [assign("b", sub(var("b"), lit(1)))]),
+                if_(var("a"), [expr(call("print_int", [var("a"), var("b")]))])
+                    .else_if(var("b"), [ret(lit(2))])
+                    .else_([ret_void()]),
+                ret(add(var("a"), mul(lit(1), lit(2)))),
+            ]),
+        ])
+    }
+
+    #[test]
+    fn lower_then_raise_round_trips() {
+        let original = sample();
+        let mut interner = Interner::new();
+        let lowered = ArenaProgram::lower(&original, &mut interner);
+        assert_eq!(lowered.raise(&interner), original);
+        assert_eq!(lowered.arena.statement_count(), 10);
+        assert_eq!(lowered.arena.expression_count(), 16);
+    }
+
+    #[test]
+    fn raise_keeps_ids_and_spans() {
+        let mut original = sample();
+        let mut line = 0;
+        original.for_each_meta_mut(&mut |meta| {
+            line += 1;
+            meta.span = Span::new(line, 2 * line);
+        });
+        let mut interner = Interner::new();
+        let lowered = ArenaProgram::lower(&original, &mut interner);
+        assert_eq!(
+            lowered.arena.expression_meta(ExprId(0)).span,
+            Span::new(6, 12)
+        );
+
+        // Would be equal if raise dropped them
+        assert_ne!(original, original.without_meta());
+        assert_eq!(lowered.raise(&interner), original);
+    }
+
+    #[test]
+    fn names_share_symbols() {
+        let mut interner = Interner::new();
+        let main = interner.intern("main");
+        let lowered = ArenaProgram::lower(&sample(), &mut interner);
+        assert_eq!(lowered.functions[1].name, main);
+
+        let a = interner.lookup("a").unwrap();
+        assert_eq!(lowered.functions[0].parameters[0].name, a);
+        assert_eq!(lowered.functions[1].parameters[0].name, a);
+    }
+}
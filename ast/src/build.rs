@@ -0,0 +1,290 @@
+// Short constructors for writing trees by hand, mainly for tests and generated programs:
+//
+//     fn_decl("main").param("a", u64()).returns(u64()).body([ret(add(var("a"), lit(1)))])
+
+use std::fmt::Display;
+
+use crate::*;
+
+// Node ids are assigned, spans are all 0:0
+pub fn program(functions: impl IntoIterator<Item = FunctionDeclaration>) -> Program {
+    let mut program = Program::new(
+        functions
+            .into_iter()
+            .map(Declaration::FunctionDeclaration)
+            .collect(),
+    );
+    program.assign_node_ids();
+    program
+}
+
+pub struct FunctionBuilder {
+    name: String,
+    parameters: Vec<FunctionParameterDeclaration>,
+    return_type: Type,
+}
+
+// Functions return void unless `returns` is called
+pub fn fn_decl(name: &str) -> FunctionBuilder {
+    FunctionBuilder {
+        name: String::from(name),
+        parameters: Vec::new(),
+        return_type: void(),
+    }
+}
+
+impl FunctionBuilder {
+    pub fn param(mut self, name: &str, datatype: Type) -> Self {
+        self.parameters.push(FunctionParameterDeclaration::new(
+            String::from(name),
+            datatype,
+        ));
+        self
+    }
+
+    pub fn returns(mut self, return_type: Type) -> Self {
+        self.return_type = return_type;
+        self
+    }
+
+    pub fn body(self, body: impl IntoIterator<Item = Statement>) -> FunctionDeclaration {
+        FunctionDeclaration::new(
+            self.name,
+            self.parameters,
+            self.return_type,
+            body.into_iter().collect(),
+        )
+    }
+}
+
+pub fn i8() -> Type {
+    Type::SimpleType(SimpleType::I8)
+}
+
+pub fn i16() -> Type {
+    Type::SimpleType(SimpleType::I16)
+}
+
+pub fn i32() -> Type {
+    Type::SimpleType(SimpleType::I32)
+}
+
+pub fn i64() -> Type {
+    Type::SimpleType(SimpleType::I64)
+}
+
+pub fn u8() -> Type {
+    Type::SimpleType(SimpleType::U8)
+}
+
+pub fn u16() -> Type {
+    Type::SimpleType(SimpleType::U16)
+}
+
+pub fn u32() -> Type {
+    Type::SimpleType(SimpleType::U32)
+}
+
+pub fn u64() -> Type {
+    Type::SimpleType(SimpleType::U64)
+}
+
+pub fn void() -> Type {
+    Type::SimpleType(SimpleType::Void)
+}
+
+pub fn decl(variable: &str, datatype: Type, value: Expression) -> Statement {
+    Statement::DeclarationStatement(DeclarationStatement::new(
+        String::from(variable),
+        datatype,
+        value,
+    ))
+}
+
+pub fn assign(variable: &str, value: Expression) -> Statement {
+    Statement::VariableAssignmentStatement(VariableAssignmentStatement::new(
+        String::from(variable),
+        value,
+    ))
+}
+
+pub fn ret(value: Expression) -> Statement {
+    Statement::ReturnStatement(Some(value), Meta::default())
+}
+
+pub fn ret_void() -> Statement {
+    Statement::ReturnStatement(None, Meta::default())
+}
+
+pub fn expr(expression: Expression) -> Statement {
+    Statement::Expression(expression)
+}
+
+pub fn while_loop(condition: Expression, body: impl IntoIterator<Item = Statement>) -> Statement {
+    Statement::WhileStatement(WhileStatement::new(condition, body.into_iter().collect()))
+}
+
+// Collects an if/else if chain, finished by `else_` or `end`:
+//
+//     if_(var("a"), [ret(lit(1))]).else_if(var("b"), [ret(lit(2))]).else_([ret(lit(3))])
+pub struct IfBuilder {
+    branches: Vec<(Expression, Vec<Statement>)>,
+}
+
+pub fn if_(condition: Expression, body: impl IntoIterator<Item = Statement>) -> IfBuilder {
+    IfBuilder {
+        branches: vec![(condition, body.into_iter().collect())],
+    }
+}
+
+impl IfBuilder {
+    pub fn else_if(
+        mut self,
+        condition: Expression,
+        body: impl IntoIterator<Item = Statement>,
+    ) -> Self {
+        self.branches.push((condition, body.into_iter().collect()));
+        self
+    }
+
+    pub fn else_(self, body: impl IntoIterator<Item = Statement>) -> Statement {
+        self.build(Some(IfStatement::make_else(body.into_iter().collect())))
+    }
+
+    pub fn end(self) -> Statement {
+        self.build(None)
+    }
+
+    fn build(self, mut tail: Option<IfStatement>) -> Statement {
+        for (condition, body) in self.branches.into_iter().rev() {
+            tail = Some(IfStatement::new(condition, body, tail));
+        }
+        Statement::IfStatement(tail.unwrap())
+    }
+}
+
+// Untyped literal, the same variant the parser produces for a number
+pub fn lit(value: impl Display) -> Expression {
+    Expression::LiteralExpression(LiteralExpression::I64(value.to_string()), Meta::default())
+}
+
+pub fn typed_lit(datatype: SimpleType, value: impl Display) -> Expression {
+    Expression::LiteralExpression(
+        LiteralExpression::new(datatype, value.to_string()),
+        Meta::default(),
+    )
+}
+
+pub fn var(name: &str) -> Expression {
+    Expression::VariableReferenceExpression(String::from(name), Meta::default())
+}
+
+pub fn call(name: &str, parameters: impl IntoIterator<Item = Expression>) -> Expression {
+    let mut call = FunctionCallExpression::new(String::from(name));
+    call.add_parameters(parameters.into_iter().collect());
+    Expression::FunctionCallExpression(call)
+}
+
+pub fn cast(value: Expression, datatype: Type) -> Expression {
+    Expression::CastExpression(CastExpression::new(value, datatype))
+}
+
+pub fn bin_op(left: Expression, op: Operator, right: Expression) -> Expression {
+    Expression::BinOpExpression(BinOpExpression::new(left, right, op))
+}
+
+pub fn add(left: Expression, right: Expression) -> Expression {
+    bin_op(left, Operator::Plus, right)
+}
+
+pub fn sub(left: Expression, right: Expression) -> Expression {
+    bin_op(left, Operator::Min, right)
+}
+
+pub fn mul(left: Expression, right: Expression) -> Expression {
+    bin_op(left, Operator::Mul, right)
+}
+
+pub fn div(left: Expression, right: Expression) -> Expression {
+    bin_op(left, Operator::Div, right)
+}
+
+pub fn rem(left: Expression, right: Expression) -> Expression {
+    bin_op(left, Operator::Mod, right)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builds_the_same_tree_as_the_constructors() {
+        let built = fn_decl("main")
+            .param("a", u64())
+            .returns(u64())
+            .body([ret(add(var("a"), lit(1)))]);
+
+        let expected = FunctionDeclaration::new(
+            String::from("main"),
+            vec![FunctionParameterDeclaration::new(
+                String::from("a"),
+                Type::SimpleType(SimpleType::U64),
+            )],
+            Type::SimpleType(SimpleType::U64),
+            vec![Statement::ReturnStatement(
+                Some(Expression::BinOpExpression(BinOpExpression::new(
+                    Expression::VariableReferenceExpression(String::from("a"), Meta::default()),
+                    Expression::LiteralExpression(
+                        LiteralExpression::I64(String::from("1")),
+                        Meta::default(),
+                    ),
+                    Operator::Plus,
+                ))),
+                Meta::default(),
+            )],
+        );
+        assert_eq!(built, expected);
+    }
+
+    #[test]
+    fn if_chains_nest_through_tail_conditions() {
+        let built = if_(var("a"), [ret(lit(1))])
+            .else_if(var("b"), [ret(lit(2))])
+            .else_([ret(lit(3))]);
+
+        let expected = Statement::IfStatement(IfStatement::new(
+            var("a"),
+            vec![ret(lit(1))],
+            Some(IfStatement::new(
+                var("b"),
+                vec![ret(lit(2))],
+                Some(IfStatement::make_else(vec![ret(lit(3))])),
+            )),
+        ));
+        assert_eq!(built, expected);
+        assert_eq!(
+            if_(var("a"), []).end(),
+            Statement::IfStatement(IfStatement::new(var("a"), Vec::new(), None))
+        );
+    }
+
+    #[test]
+    fn generated_programs() {
+        // f0 returns 0 and every other fi returns f(i - 1)() + i
+        let functions = (0..10).map(|i| {
+            let body = if i == 0 {
+                ret(lit(0))
+            } else {
+                ret(add(call(&format!("f{}", i - 1), []), lit(i)))
+            };
+            fn_decl(&format!("f{}", i)).returns(u64()).body([body])
+        });
+        // program numbers the nodes, the built statements below have no ids
+        let generated = program(functions).without_meta();
+
+        assert_eq!(generated.declarations.len(), 10);
+        let Declaration::FunctionDeclaration(last) = &generated.declarations[9];
+        assert_eq!(last.name, "f9");
+        assert_eq!(last.body, vec![ret(add(call("f8", []), lit(9)))]);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::*;
+
+// Renders a program as a Graphviz graph, one node per AST node with edges labelled by the field
+// that holds the child
+pub fn program_to_dot(program: &Program) -> String {
+    let mut writer = DotWriter::new();
+    writer.program(program);
+    writer.finish()
+}
+
+struct DotWriter {
+    out: String,
+    next_id: usize,
+}
+
+impl DotWriter {
+    fn new() -> Self {
+        Self {
+            out: String::from("digraph ast {\n    node [shape=box, fontname=\"monospace\"];\n"),
+            next_id: 0,
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.out.push_str("}\n");
+        self.out
+    }
+
+    fn node(&mut self, label: &str) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.out
+            .push_str(format!("    n{} [label=\"{}\"];\n", id, escape(label)).as_str());
+        id
+    }
+
+    fn edge(&mut self, from: usize, to: usize, label: &str) {
+        self.out.push_str(
+            format!("    n{} -> n{} [label=\"{}\"];\n", from, to, escape(label)).as_str(),
+        );
+    }
+
+    fn program(&mut self, program: &Program) {
+        let id = self.node("Program");
+        for (i, declaration) in program.declarations.iter().enumerate() {
+            let child = match declaration {
+                Declaration::FunctionDeclaration(function) => self.function(function),
+            };
+            self.edge(id, child, format!("declarations[{}]", i).as_str());
+        }
+    }
+
+    fn function(&mut self, function: &FunctionDeclaration) -> usize {
+        let parameters: Vec<String> = function
+            .parameters
+            .iter()
+            .map(|p| format!("{}: {}", p.name, p.datatype))
+            .collect();
+        let id = self.node(
+            format!(
+                "FunctionDeclaration\nfn {}({}) -> {}",
+                function.name,
+                parameters.join(", "),
+                function.return_type
+            )
+            .as_str(),
+        );
+        self.body(id, &function.body);
+        id
+    }
+
+    fn body(&mut self, parent: usize, body: &[Statement]) {
+        for (i, statement) in body.iter().enumerate() {
+            let child = self.statement(statement);
+            self.edge(parent, child, format!("body[{}]", i).as_str());
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) -> usize {
+        match statement {
+            Statement::DeclarationStatement(declaration) => {
+                let id = self.node(
+                    format!(
+                        "DeclarationStatement\n{}: {}",
+                        declaration.variable, declaration.datatype
+                    )
+                    .as_str(),
+                );
+                let value = self.expression(&declaration.value);
+                self.edge(id, value, "value");
+                id
+            }
+            Statement::VariableAssignmentStatement(assignment) => {
+                let id = self
+                    .node(format!("VariableAssignmentStatement\n{}", assignment.variable).as_str());
+                let value = self.expression(&assignment.value);
+                self.edge(id, value, "value");
+                id
+            }
+            Statement::IfStatement(if_statement) => self.if_statement(if_statement),
+            Statement::WhileStatement(while_statement) => {
+                let id = self.node("WhileStatement");
+                let condition = self.expression(&while_statement.condition);
+                self.edge(id, condition, "condition");
+                self.body(id, &while_statement.body);
+                id
+            }
+            Statement::ReturnStatement(value, _) => {
+                let id = self.node("ReturnStatement");
+                if let Some(value) = value {
+                    let value = self.expression(value);
+                    self.edge(id, value, "value");
+                }
+                id
+            }
+            Statement::Expression(expression) => self.expression(expression),
+        }
+    }
+
+    fn if_statement(&mut self, if_statement: &IfStatement) -> usize {
+        let id = match &if_statement.condition {
+            Some(condition) => {
+                let id = self.node("IfStatement");
+                let condition = self.expression(condition);
+                self.edge(id, condition, "condition");
+                id
+            }
+            None => self.node("IfStatement\nelse"),
+        };
+        self.body(id, &if_statement.body);
+        if let Some(tail) = &if_statement.tail_conditions {
+            let tail = self.if_statement(tail);
+            self.edge(id, tail, "tail_conditions");
+        }
+        id
+    }
+
+    fn expression(&mut self, expression: &Expression) -> usize {
+        match expression {
+            Expression::BinOpExpression(bin_op) => {
+                let id = self.node(format!("BinOpExpression\n{}", bin_op.op).as_str());
+                let left = self.expression(&bin_op.left);
+                self.edge(id, left, "left");
+                let right = self.expression(&bin_op.right);
+                self.edge(id, right, "right");
+                id
+            }
+            Expression::LiteralExpression(literal, _) => self.node(
+                format!(
+                    "LiteralExpression\n{}: {}",
+                    literal.value(),
+                    literal.datatype()
+                )
+                .as_str(),
+            ),
+            Expression::VariableReferenceExpression(name, _) => {
+                self.node(format!("VariableReferenceExpression\n{}", name).as_str())
+            }
+            Expression::FunctionCallExpression(call) => {
+                let id = self.node(format!("FunctionCallExpression\n{}", call.name).as_str());
+                for (i, parameter) in call.parameters.iter().enumerate() {
+                    let child = self.expression(parameter);
+                    self.edge(id, child, format!("parameters[{}]", i).as_str());
+                }
+                id
+            }
+            Expression::CastExpression(cast) => {
+                let id = self.node(format!("CastExpression\n{}", cast.datatype).as_str());
+                let value = self.expression(&cast.value);
+                self.edge(id, value, "value");
+                id
+            }
+        }
+    }
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn precedence_and_else_if_edges() {
+        // fn main() -> u64 { if a { return 1 + 2 * 3; } else if b {} else {} }
+        let product = BinOpExpression::new(
+            Expression::LiteralExpression(
+                LiteralExpression::I64(String::from("2")),
+                Meta::default(),
+            ),
+            Expression::LiteralExpression(
+                LiteralExpression::I64(String::from("3")),
+                Meta::default(),
+            ),
+            Operator::Mul,
+        );
+        let sum = BinOpExpression::new(
+            Expression::LiteralExpression(
+                LiteralExpression::I64(String::from("1")),
+                Meta::default(),
+            ),
+            Expression::BinOpExpression(product),
+            Operator::Plus,
+        );
+        let else_statement = IfStatement::make_else(Vec::new());
+        let else_if_statement = IfStatement::new(
+            Expression::VariableReferenceExpression(String::from("b"), Meta::default()),
+            Vec::new(),
+            Some(else_statement),
+        );
+        let if_statement = IfStatement::new(
+            Expression::VariableReferenceExpression(String::from("a"), Meta::default()),
+            vec![Statement::ReturnStatement(
+                Some(Expression::BinOpExpression(sum)),
+                Meta::default(),
+            )],
+            Some(else_if_statement),
+        );
+        let function = FunctionDeclaration::new(
+            String::from("main"),
+            Vec::new(),
+            Type::SimpleType(SimpleType::U64),
+            vec![Statement::IfStatement(if_statement)],
+        );
+        let program = Program::new(vec![Declaration::FunctionDeclaration(function)]);
+
+        let dot = program_to_dot(&program);
+
+        assert!(dot.starts_with("digraph ast {"));
+        assert!(dot.contains("n0 [label=\"Program\"];"));
+        assert!(dot.contains("n1 [label=\"FunctionDeclaration\\nfn main() -> u64\"];"));
+        assert!(dot.contains("n2 -> n3 [label=\"condition\"];"));
+        assert!(dot.contains("n5 [label=\"BinOpExpression\\n+\"];"));
+        assert!(dot.contains("n5 -> n6 [label=\"left\"];"));
+        assert!(dot.contains("n7 [label=\"BinOpExpression\\n*\"];"));
+        assert!(dot.contains("n5 -> n7 [label=\"right\"];"));
+        assert!(dot.contains("n2 -> n10 [label=\"tail_conditions\"];"));
+        assert!(dot.contains("n12 [label=\"IfStatement\\nelse\"];"));
+        assert!(dot.contains("n10 -> n12 [label=\"tail_conditions\"];"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
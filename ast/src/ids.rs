@@ -0,0 +1,107 @@
+use crate::*;
+
+impl Program {
+    // Numbers every node in preorder starting at 1. The parser calls this once it is done, trees
+    // built or rewritten by hand should call it again before being handed to a pass
+    pub fn assign_node_ids(&mut self) {
+        let mut next = 0;
+        self.for_each_meta_mut(&mut |meta| {
+            next += 1;
+            meta.id = NodeId(next);
+        });
+    }
+
+    // Visits the Meta of every node in preorder
+    pub fn for_each_meta_mut(&mut self, f: &mut dyn FnMut(&mut Meta)) {
+        for declaration in &mut self.declarations {
+            match declaration {
+                Declaration::FunctionDeclaration(function) => function_metas(function, f),
+            }
+        }
+    }
+
+    // The tree with every node id and span reset, for comparing a parsed tree to a hand built one
+    pub fn without_meta(&self) -> Program {
+        let mut program = self.clone();
+        program.for_each_meta_mut(&mut |meta| *meta = Meta::default());
+        program
+    }
+
+    // Equal apart from node ids and spans
+    pub fn structurally_eq(&self, other: &Program) -> bool {
+        self.without_meta() == other.without_meta()
+    }
+}
+
+fn function_metas(function: &mut FunctionDeclaration, f: &mut dyn FnMut(&mut Meta)) {
+    f(&mut function.meta);
+    for parameter in &mut function.parameters {
+        f(&mut parameter.meta);
+    }
+    body_metas(&mut function.body, f);
+}
+
+fn body_metas(body: &mut [Statement], f: &mut dyn FnMut(&mut Meta)) {
+    for statement in body {
+        statement_metas(statement, f);
+    }
+}
+
+fn statement_metas(statement: &mut Statement, f: &mut dyn FnMut(&mut Meta)) {
+    match statement {
+        Statement::DeclarationStatement(declaration) => {
+            f(&mut declaration.meta);
+            expression_metas(&mut declaration.value, f);
+        }
+        Statement::VariableAssignmentStatement(assignment) => {
+            f(&mut assignment.meta);
+            expression_metas(&mut assignment.value, f);
+        }
+        Statement::IfStatement(if_statement) => if_metas(if_statement, f),
+        Statement::WhileStatement(while_statement) => {
+            f(&mut while_statement.meta);
+            expression_metas(&mut while_statement.condition, f);
+            body_metas(&mut while_statement.body, f);
+        }
+        Statement::ReturnStatement(value, meta) => {
+            f(meta);
+            if let Some(value) = value {
+                expression_metas(value, f);
+            }
+        }
+        Statement::Expression(expression) => expression_metas(expression, f),
+    }
+}
+
+fn if_metas(if_statement: &mut IfStatement, f: &mut dyn FnMut(&mut Meta)) {
+    f(&mut if_statement.meta);
+    if let Some(condition) = &mut if_statement.condition {
+        expression_metas(condition, f);
+    }
+    body_metas(&mut if_statement.body, f);
+    if let Some(tail) = &mut if_statement.tail_conditions {
+        if_metas(tail, f);
+    }
+}
+
+fn expression_metas(expression: &mut Expression, f: &mut dyn FnMut(&mut Meta)) {
+    match expression {
+        Expression::BinOpExpression(bin_op) => {
+            f(&mut bin_op.meta);
+            expression_metas(&mut bin_op.left, f);
+            expression_metas(&mut bin_op.right, f);
+        }
+        Expression::LiteralExpression(_, meta) => f(meta),
+        Expression::VariableReferenceExpression(_, meta) => f(meta),
+        Expression::FunctionCallExpression(call) => {
+            f(&mut call.meta);
+            for parameter in &mut call.parameters {
+                expression_metas(parameter, f);
+            }
+        }
+        Expression::CastExpression(cast) => {
+            f(&mut cast.meta);
+            expression_metas(&mut cast.value, f);
+        }
+    }
+}
@@ -1,4 +1,62 @@
-#[derive(Debug)]
+use std::fmt::{Debug, Display};
+
+pub mod arena;
+pub mod build;
+pub mod dot;
+mod ids;
+
+// Start of a node in the source, zero based like the lexer's line number and index
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, column: usize) -> Self {
+        Self { line, column }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// Unique per node once Program::assign_node_ids has run, 0 means unassigned
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeId(pub u32);
+
+// Identity and position of a node. Trees only compare equal with the same ids and spans, use
+// Program::structurally_eq to compare a parsed tree to a hand built one
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Meta {
+    pub id: NodeId,
+    pub span: Span,
+}
+
+impl Meta {
+    pub fn at(span: Span) -> Self {
+        Self {
+            id: NodeId::default(),
+            span,
+        }
+    }
+}
+
+// Printed as #id@line:column to keep Debug output of whole trees readable
+impl Debug for Meta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}@{}", self.id.0, self.span)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Program {
     pub declarations: Vec<Declaration>,
 }
@@ -9,17 +67,20 @@ impl Program {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Declaration {
     FunctionDeclaration(FunctionDeclaration),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FunctionDeclaration {
     pub name: String,
     pub parameters: Vec<FunctionParameterDeclaration>,
     pub return_type: Type,
     pub body: Vec<Statement>,
+    pub meta: Meta,
 }
 
 impl FunctionDeclaration {
@@ -34,62 +95,89 @@ impl FunctionDeclaration {
             parameters,
             return_type,
             body,
+            meta: Meta::default(),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FunctionParameterDeclaration {
     pub name: String,
     pub datatype: Type,
+    pub meta: Meta,
 }
 
 impl FunctionParameterDeclaration {
     pub fn new(name: String, datatype: Type) -> Self {
-        Self { name, datatype }
+        Self {
+            name,
+            datatype,
+            meta: Meta::default(),
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Statement {
     DeclarationStatement(DeclarationStatement),
     VariableAssignmentStatement(VariableAssignmentStatement),
     IfStatement(IfStatement),
     WhileStatement(WhileStatement),
-    ReturnStatement(Option<Expression>),
+    ReturnStatement(Option<Expression>, Meta),
     Expression(Expression),
 }
 
-#[derive(Debug)]
+impl Statement {
+    pub fn meta(&self) -> &Meta {
+        match self {
+            Statement::DeclarationStatement(declaration) => &declaration.meta,
+            Statement::VariableAssignmentStatement(assignment) => &assignment.meta,
+            Statement::IfStatement(if_statement) => &if_statement.meta,
+            Statement::WhileStatement(while_statement) => &while_statement.meta,
+            Statement::ReturnStatement(_, meta) => meta,
+            Statement::Expression(expression) => expression.meta(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct WhileStatement {
     pub condition: Expression,
     pub body: Vec<Statement>,
+    pub meta: Meta,
 }
 
 impl WhileStatement {
     pub fn new(condition: Expression, body: Vec<Statement>) -> Self {
-        Self { condition, body }
+        Self {
+            condition,
+            body,
+            meta: Meta::default(),
+        }
     }
 }
 
 // if condition is None -> else statement
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IfStatement {
     pub condition: Option<Expression>,
     pub body: Vec<Statement>,
     pub tail_conditions: Option<Box<IfStatement>>,
+    pub meta: Meta,
 }
 
 impl IfStatement {
     pub fn new(condition: Expression, body: Vec<Statement>, tail: Option<IfStatement>) -> Self {
-        let tail = match tail {
-            Some(tail) => Some(Box::new(tail)),
-            None => None,
-        };
+        let tail = tail.map(Box::new);
         Self {
             condition: Some(condition),
             body,
             tail_conditions: tail,
+            meta: Meta::default(),
         }
     }
 
@@ -98,15 +186,18 @@ impl IfStatement {
             condition: None,
             body,
             tail_conditions: None,
+            meta: Meta::default(),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DeclarationStatement {
     pub variable: String,
     pub datatype: Type,
     pub value: Expression,
+    pub meta: Meta,
 }
 
 impl DeclarationStatement {
@@ -115,28 +206,37 @@ impl DeclarationStatement {
             variable,
             datatype,
             value,
+            meta: Meta::default(),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VariableAssignmentStatement {
     pub variable: String,
     pub value: Expression,
+    pub meta: Meta,
 }
 
 impl VariableAssignmentStatement {
     pub fn new(variable: String, value: Expression) -> Self {
-        Self { variable, value }
+        Self {
+            variable,
+            value,
+            meta: Meta::default(),
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
     SimpleType(SimpleType),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SimpleType {
     I8,
     I16,
@@ -149,22 +249,114 @@ pub enum SimpleType {
     Void,
 }
 
-#[derive(Debug)]
+impl SimpleType {
+    // Smallest and largest value of an integer type, None for void
+    pub fn range(&self) -> Option<(i128, i128)> {
+        let range = match self {
+            SimpleType::I8 => (i8::MIN as i128, i8::MAX as i128),
+            SimpleType::I16 => (i16::MIN as i128, i16::MAX as i128),
+            SimpleType::I32 => (i32::MIN as i128, i32::MAX as i128),
+            SimpleType::I64 => (i64::MIN as i128, i64::MAX as i128),
+            SimpleType::U8 => (0, u8::MAX as i128),
+            SimpleType::U16 => (0, u16::MAX as i128),
+            SimpleType::U32 => (0, u32::MAX as i128),
+            SimpleType::U64 => (0, u64::MAX as i128),
+            SimpleType::Void => return None,
+        };
+        Some(range)
+    }
+
+    pub fn is_signed(&self) -> bool {
+        matches!(
+            self,
+            SimpleType::I8 | SimpleType::I16 | SimpleType::I32 | SimpleType::I64
+        )
+    }
+
+    // Width in bits, 0 for void
+    pub fn bits(&self) -> u32 {
+        match self {
+            SimpleType::I8 | SimpleType::U8 => 8,
+            SimpleType::I16 | SimpleType::U16 => 16,
+            SimpleType::I32 | SimpleType::U32 => 32,
+            SimpleType::I64 | SimpleType::U64 => 64,
+            SimpleType::Void => 0,
+        }
+    }
+
+    // Converts any integer to this type the way an `as` cast does: keep the low bits() bits,
+    // then sign extend them for signed types and zero extend them for unsigned ones. Values
+    // already in range are unchanged, so widening never changes a value
+    pub fn wrap(&self, value: i128) -> i128 {
+        let bits = self.bits();
+        assert!(bits > 0, "There are no void values");
+        let low = value & ((1i128 << bits) - 1);
+        if self.is_signed() && low >= 1i128 << (bits - 1) {
+            low - (1i128 << bits)
+        } else {
+            low
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::SimpleType(simple_type) => write!(f, "{}", simple_type),
+        }
+    }
+}
+
+impl Display for SimpleType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            SimpleType::I8 => "i8",
+            SimpleType::I16 => "i16",
+            SimpleType::I32 => "i32",
+            SimpleType::I64 => "i64",
+            SimpleType::U8 => "u8",
+            SimpleType::U16 => "u16",
+            SimpleType::U32 => "u32",
+            SimpleType::U64 => "u64",
+            SimpleType::Void => "void",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expression {
     BinOpExpression(BinOpExpression),
-    LiteralExpression(LiteralExpression),
-    VariableReferenceExpression(String),
+    LiteralExpression(LiteralExpression, Meta),
+    VariableReferenceExpression(String, Meta),
     FunctionCallExpression(FunctionCallExpression),
+    CastExpression(CastExpression),
 }
 
-#[derive(Debug)]
+impl Expression {
+    pub fn meta(&self) -> &Meta {
+        match self {
+            Expression::BinOpExpression(bin_op) => &bin_op.meta,
+            Expression::LiteralExpression(_, meta) => meta,
+            Expression::VariableReferenceExpression(_, meta) => meta,
+            Expression::FunctionCallExpression(call) => &call.meta,
+            Expression::CastExpression(cast) => &cast.meta,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BinOpExpression {
     pub left: Box<Expression>,
     pub op: Operator,
     pub right: Box<Expression>,
+    pub meta: Meta,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Operator {
     Plus,
     Min,
@@ -173,20 +365,73 @@ pub enum Operator {
     Mod,
 }
 
+impl Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            Operator::Plus => "+",
+            Operator::Min => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Mod => "%",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+// Why an operation has no result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trap {
+    DivisionByZero,
+    // Only the quotient or remainder of the smallest signed value and -1, which does not fit
+    Overflow,
+}
+
+impl Operator {
+    // Applies the operator to two values of datatype. +, - and * wrap around like two's
+    // complement hardware does, / rounds towards zero and % takes the sign of the left operand.
+    // Division traps on a zero divisor and on MIN / -1, for % as well since x86 does the same
+    pub fn evaluate(&self, datatype: SimpleType, left: i128, right: i128) -> Result<i128, Trap> {
+        let value = match self {
+            // Only the low bits survive wrap, so wrapping in i128 first gives the same result
+            Operator::Plus => left.wrapping_add(right),
+            Operator::Min => left.wrapping_sub(right),
+            Operator::Mul => left.wrapping_mul(right),
+            Operator::Div | Operator::Mod => {
+                if right == 0 {
+                    return Err(Trap::DivisionByZero);
+                }
+                let (min, _) = datatype.range().unwrap();
+                if datatype.is_signed() && left == min && right == -1 {
+                    return Err(Trap::Overflow);
+                }
+                if *self == Operator::Div {
+                    left / right
+                } else {
+                    left % right
+                }
+            }
+        };
+        Ok(datatype.wrap(value))
+    }
+}
+
 impl BinOpExpression {
     pub fn new(left: Expression, right: Expression, op: Operator) -> Self {
         Self {
             left: Box::new(left),
             op,
             right: Box::new(right),
+            meta: Meta::default(),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FunctionCallExpression {
     pub name: String,
     pub parameters: Vec<Expression>,
+    pub meta: Meta,
 }
 
 impl FunctionCallExpression {
@@ -194,6 +439,7 @@ impl FunctionCallExpression {
         Self {
             name,
             parameters: Vec::new(),
+            meta: Meta::default(),
         }
     }
 
@@ -208,7 +454,27 @@ impl FunctionCallExpression {
     }
 }
 
-#[derive(Debug)]
+// value as datatype, binds tighter than any binary operator
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CastExpression {
+    pub value: Box<Expression>,
+    pub datatype: Type,
+    pub meta: Meta,
+}
+
+impl CastExpression {
+    pub fn new(value: Expression, datatype: Type) -> Self {
+        Self {
+            value: Box::new(value),
+            datatype,
+            meta: Meta::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LiteralExpression {
     I8(String),
     I16(String),
@@ -219,3 +485,132 @@ pub enum LiteralExpression {
     U32(String),
     U64(String),
 }
+
+impl LiteralExpression {
+    pub fn new(datatype: SimpleType, value: String) -> Self {
+        match datatype {
+            SimpleType::I8 => LiteralExpression::I8(value),
+            SimpleType::I16 => LiteralExpression::I16(value),
+            SimpleType::I32 => LiteralExpression::I32(value),
+            SimpleType::I64 => LiteralExpression::I64(value),
+            SimpleType::U8 => LiteralExpression::U8(value),
+            SimpleType::U16 => LiteralExpression::U16(value),
+            SimpleType::U32 => LiteralExpression::U32(value),
+            SimpleType::U64 => LiteralExpression::U64(value),
+            SimpleType::Void => panic!("There are no void literals"),
+        }
+    }
+
+    pub fn datatype(&self) -> SimpleType {
+        match self {
+            LiteralExpression::I8(_) => SimpleType::I8,
+            LiteralExpression::I16(_) => SimpleType::I16,
+            LiteralExpression::I32(_) => SimpleType::I32,
+            LiteralExpression::I64(_) => SimpleType::I64,
+            LiteralExpression::U8(_) => SimpleType::U8,
+            LiteralExpression::U16(_) => SimpleType::U16,
+            LiteralExpression::U32(_) => SimpleType::U32,
+            LiteralExpression::U64(_) => SimpleType::U64,
+        }
+    }
+
+    pub fn value_mut(&mut self) -> &mut String {
+        match self {
+            LiteralExpression::I8(value)
+            | LiteralExpression::I16(value)
+            | LiteralExpression::I32(value)
+            | LiteralExpression::I64(value)
+            | LiteralExpression::U8(value)
+            | LiteralExpression::U16(value)
+            | LiteralExpression::U32(value)
+            | LiteralExpression::U64(value) => value,
+        }
+    }
+
+    // The digits as written in the source
+    pub fn value(&self) -> &str {
+        match self {
+            LiteralExpression::I8(value)
+            | LiteralExpression::I16(value)
+            | LiteralExpression::I32(value)
+            | LiteralExpression::I64(value)
+            | LiteralExpression::U8(value)
+            | LiteralExpression::U16(value)
+            | LiteralExpression::U32(value)
+            | LiteralExpression::U64(value) => value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn arithmetic_wraps_and_division_traps() {
+        let u8 = SimpleType::U8;
+        let i8 = SimpleType::I8;
+        assert_eq!(Operator::Plus.evaluate(u8, 255, 1), Ok(0));
+        assert_eq!(Operator::Min.evaluate(u8, 0, 1), Ok(255));
+        assert_eq!(Operator::Mul.evaluate(i8, 64, 2), Ok(-128));
+        assert_eq!(Operator::Plus.evaluate(i8, 127, 1), Ok(-128));
+        let u64_max = u64::MAX as i128;
+        assert_eq!(
+            Operator::Mul.evaluate(SimpleType::U64, u64_max, u64_max),
+            Ok(1)
+        );
+        assert_eq!(Operator::Div.evaluate(i8, -7, 2), Ok(-3));
+        assert_eq!(Operator::Mod.evaluate(i8, -7, 2), Ok(-1));
+        assert_eq!(Operator::Mod.evaluate(i8, 7, -2), Ok(1));
+        assert_eq!(Operator::Div.evaluate(u8, 1, 0), Err(Trap::DivisionByZero));
+        assert_eq!(Operator::Mod.evaluate(u8, 1, 0), Err(Trap::DivisionByZero));
+        assert_eq!(Operator::Div.evaluate(i8, -128, -1), Err(Trap::Overflow));
+        assert_eq!(Operator::Mod.evaluate(i8, -128, -1), Err(Trap::Overflow));
+        assert_eq!(Operator::Div.evaluate(SimpleType::I16, -128, -1), Ok(128));
+    }
+
+    #[test]
+    fn casts_truncate_and_extend() {
+        // Narrowing keeps the low bits
+        assert_eq!(SimpleType::U8.wrap(300), 44);
+        assert_eq!(SimpleType::U16.wrap(0x1_2345), 0x2345);
+        assert_eq!(SimpleType::I8.wrap(200), -56);
+        assert_eq!(SimpleType::I8.wrap(-129), 127);
+        // Signed to unsigned reinterprets the bits, -1 is all ones
+        assert_eq!(SimpleType::U8.wrap(-1), 255);
+        assert_eq!(SimpleType::U64.wrap(-1), u64::MAX as i128);
+        assert_eq!(SimpleType::U32.wrap(i64::MIN as i128), 0);
+        // Unsigned to signed sign extends from the top bit of the new width
+        assert_eq!(SimpleType::I16.wrap(u16::MAX as i128), -1);
+        assert_eq!(SimpleType::I64.wrap(u64::MAX as i128), -1);
+        assert_eq!(SimpleType::I32.wrap(0x8000_0000), i32::MIN as i128);
+        // Widening keeps the value, so a negative i8 stays negative as an i64 and a u8 is zero
+        // extended
+        for value in [-128, -1, 0, 1, 127] {
+            assert_eq!(SimpleType::I64.wrap(value), value);
+        }
+        assert_eq!(SimpleType::U64.wrap(255), 255);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip_keeps_ids_and_spans() {
+        use build::*;
+
+        let mut program = program([fn_decl("main")
+            .param("a", u64())
+            .returns(u64())
+            .body([ret(add(var("a"), cast(lit(1), u64())))])]);
+        let mut column = 0;
+        program.for_each_meta_mut(&mut |meta| {
+            column += 3;
+            meta.span = Span::new(1, column);
+        });
+
+        let json = serde_json::to_string(&program).unwrap();
+        assert!(json.contains(
+            r#"{"VariableReferenceExpression":["a",{"id":5,"span":{"line":1,"column":15}}]}"#
+        ));
+        assert_eq!(serde_json::from_str::<Program>(&json).unwrap(), program);
+    }
+}
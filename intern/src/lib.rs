@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+// Handle to a string stored in an Interner, cheap to copy and compare
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Interner {
+    symbols: HashMap<String, Symbol>,
+    strings: Vec<String>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, string: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(string) {
+            return *symbol;
+        }
+        let symbol = Symbol(self.strings.len() as u32);
+        self.strings.push(string.to_string());
+        self.symbols.insert(string.to_string(), symbol);
+        symbol
+    }
+
+    pub fn lookup(&self, string: &str) -> Option<Symbol> {
+        self.symbols.get(string).copied()
+    }
+
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.strings[symbol.index()]
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    // Rough bytes allocated for the table, every string is stored twice and the map counts one
+    // entry per slot it has room for
+    pub fn heap_bytes(&self) -> usize {
+        let strings: usize = self.strings.iter().map(String::capacity).sum();
+        self.strings.capacity() * size_of::<String>()
+            + self.symbols.capacity() * size_of::<(String, Symbol)>()
+            + 2 * strings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn same_string_same_symbol() {
+        let mut interner = Interner::new();
+        let a = interner.intern("main");
+        let b = interner.intern("print_int");
+        assert_eq!(interner.intern("main"), a);
+        assert_ne!(a, b);
+        assert_eq!(interner.resolve(b), "print_int");
+        assert_eq!(interner.lookup("missing"), None);
+        assert_eq!(interner.len(), 2);
+    }
+}
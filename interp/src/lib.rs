@@ -0,0 +1,454 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Write},
+    thread,
+};
+
+use analysis::entry::EntryPoint;
+use ast::*;
+use resolve::builtins::Builtin;
+
+// Deeper recursion than this is reported as a stack overflow instead of crashing the host, run
+// makes sure the host stack is big enough to get there
+const MAX_CALL_DEPTH: usize = 10000;
+const HOST_STACK_SIZE: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Value {
+    pub datatype: SimpleType,
+    pub value: i128,
+}
+
+impl Value {
+    pub fn new(datatype: SimpleType, value: i128) -> Self {
+        Self {
+            datatype,
+            value: datatype.wrap(value),
+        }
+    }
+}
+
+// Runs a program by walking its tree. The program has to have passed the type checker and had
+// its literals rewritten, every value then carries the type the checker gave it and arithmetic
+// follows Operator::evaluate. A trap ends the run with an error naming where it happened.
+// Builtins read from input and write to output, stdin and stdout unless given to with_io.
+pub struct Interpreter<'a> {
+    functions: HashMap<&'a str, &'a FunctionDeclaration>,
+    depth: usize,
+    input: Box<dyn BufRead + Send + 'a>,
+    output: Box<dyn Write + Send + 'a>,
+}
+
+// Locals of one call, innermost block last
+struct Frame {
+    scopes: Vec<HashMap<String, Value>>,
+}
+
+impl Frame {
+    fn lookup(&mut self, name: &str) -> &mut Value {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+            .expect("variables are resolved before running")
+    }
+}
+
+enum Flow {
+    Next,
+    Return(Option<Value>),
+}
+
+// Why the program ended before main returned
+enum Stop {
+    Trap(String),
+    Exit(i32),
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        Self::with_io(
+            program,
+            Box::new(BufReader::new(io::stdin())),
+            Box::new(io::stdout()),
+        )
+    }
+
+    pub fn with_io(
+        program: &'a Program,
+        input: Box<dyn BufRead + Send + 'a>,
+        output: Box<dyn Write + Send + 'a>,
+    ) -> Self {
+        let functions = program
+            .declarations
+            .iter()
+            .map(|declaration| {
+                let Declaration::FunctionDeclaration(function) = declaration;
+                (function.name.as_str(), function)
+            })
+            .collect();
+        Self {
+            functions,
+            depth: 0,
+            input,
+            output,
+        }
+    }
+
+    // Calls main on a thread with a large stack and returns the exit status of the process
+    pub fn run(&mut self, entry: &EntryPoint, argc: usize) -> Result<i32, String> {
+        let arguments = match entry.argc {
+            Some(Type::SimpleType(datatype)) => vec![Value::new(datatype, argc as i128)],
+            None => Vec::new(),
+        };
+        let result = thread::scope(|scope| {
+            thread::Builder::new()
+                .stack_size(HOST_STACK_SIZE)
+                .spawn_scoped(scope, || self.call("main", arguments, Span::default()))
+                .expect("could not start the interpreter thread")
+                .join()
+                .expect("the interpreter thread panicked")
+        });
+        let status = match result {
+            Ok(value) => Ok(entry.exit_status(value.map(|value| value.value))),
+            Err(Stop::Exit(status)) => Ok(status),
+            Err(Stop::Trap(error)) => Err(error),
+        };
+        self.output
+            .flush()
+            .map_err(|error| format!("Could not write the output: {}", error))?;
+        status
+    }
+
+    // span is where the call is, for traps in builtins
+    fn call(
+        &mut self,
+        name: &str,
+        arguments: Vec<Value>,
+        span: Span,
+    ) -> Result<Option<Value>, Stop> {
+        if let Some(builtin) = Builtin::by_name(name) {
+            return self.builtin(builtin, arguments, span);
+        }
+        let function = self.functions[name];
+        if self.depth == MAX_CALL_DEPTH {
+            return Err(Stop::Trap(format!(
+                "Stack overflow calling \"{}\" at {}",
+                name, function.meta.span
+            )));
+        }
+
+        let mut frame = Frame {
+            scopes: vec![
+                function
+                    .parameters
+                    .iter()
+                    .zip(arguments)
+                    .map(|(parameter, argument)| (parameter.name.clone(), argument))
+                    .collect(),
+            ],
+        };
+        self.depth += 1;
+        let flow = self.body(&mut frame, &function.body);
+        self.depth -= 1;
+        match flow? {
+            Flow::Return(value) => Ok(value),
+            Flow::Next => Ok(None),
+        }
+    }
+
+    fn builtin(
+        &mut self,
+        builtin: &Builtin,
+        arguments: Vec<Value>,
+        span: Span,
+    ) -> Result<Option<Value>, Stop> {
+        let written = match builtin.name {
+            "print_int" | "print_uint" => writeln!(self.output, "{}", arguments[0].value),
+            "print_char" => self.output.write_all(&[arguments[0].value as u8]),
+            "read_int" => {
+                let mut line = String::new();
+                self.input.read_line(&mut line).map_err(|error| {
+                    Stop::Trap(format!("Could not read the input at {}: {}", span, error))
+                })?;
+                if line.is_empty() {
+                    return Err(Stop::Trap(format!(
+                        "read_int at {} reached the end of the input",
+                        span
+                    )));
+                }
+                let value = line.trim().parse::<i64>().map_err(|_| {
+                    Stop::Trap(format!(
+                        "read_int at {} expected an integer but read \"{}\"",
+                        span,
+                        line.trim()
+                    ))
+                })?;
+                return Ok(Some(Value::new(SimpleType::I64, value as i128)));
+            }
+            "exit" => return Err(Stop::Exit(arguments[0].value as i32)),
+            _ => unreachable!("every builtin is implemented"),
+        };
+        written.map_err(|error| Stop::Trap(format!("Could not write the output: {}", error)))?;
+        Ok(None)
+    }
+
+    fn body(&mut self, frame: &mut Frame, body: &[Statement]) -> Result<Flow, Stop> {
+        frame.scopes.push(HashMap::new());
+        let mut flow = Ok(Flow::Next);
+        for statement in body {
+            flow = self.statement(frame, statement);
+            if !matches!(flow, Ok(Flow::Next)) {
+                break;
+            }
+        }
+        frame.scopes.pop();
+        flow
+    }
+
+    fn statement(&mut self, frame: &mut Frame, statement: &Statement) -> Result<Flow, Stop> {
+        match statement {
+            Statement::DeclarationStatement(declaration) => {
+                let value = self.value(frame, &declaration.value)?;
+                frame
+                    .scopes
+                    .last_mut()
+                    .unwrap()
+                    .insert(declaration.variable.clone(), value);
+            }
+            Statement::VariableAssignmentStatement(assignment) => {
+                let value = self.value(frame, &assignment.value)?;
+                *frame.lookup(&assignment.variable) = value;
+            }
+            Statement::IfStatement(if_statement) => {
+                let mut next = Some(if_statement);
+                while let Some(if_statement) = next {
+                    let taken = match &if_statement.condition {
+                        Some(condition) => self.value(frame, condition)?.value != 0,
+                        None => true,
+                    };
+                    if taken {
+                        return self.body(frame, &if_statement.body);
+                    }
+                    next = if_statement.tail_conditions.as_deref();
+                }
+            }
+            Statement::WhileStatement(while_statement) => {
+                while self.value(frame, &while_statement.condition)?.value != 0 {
+                    if let Flow::Return(value) = self.body(frame, &while_statement.body)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+            }
+            Statement::ReturnStatement(value, _) => {
+                let value = match value {
+                    Some(value) => self.expression(frame, value)?,
+                    None => None,
+                };
+                return Ok(Flow::Return(value));
+            }
+            Statement::Expression(expression) => {
+                self.expression(frame, expression)?;
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn value(&mut self, frame: &mut Frame, expression: &Expression) -> Result<Value, Stop> {
+        Ok(self
+            .expression(frame, expression)?
+            .expect("void is not used as a value after type checking"))
+    }
+
+    // None for a call to a void function
+    fn expression(
+        &mut self,
+        frame: &mut Frame,
+        expression: &Expression,
+    ) -> Result<Option<Value>, Stop> {
+        let value = match expression {
+            Expression::BinOpExpression(bin_op) => {
+                let left = self.value(frame, &bin_op.left)?;
+                let right = self.value(frame, &bin_op.right)?;
+                let value = bin_op
+                    .op
+                    .evaluate(left.datatype, left.value, right.value)
+                    .map_err(|trap| {
+                        Stop::Trap(match trap {
+                            Trap::DivisionByZero => {
+                                format!("Division by zero at {}", bin_op.meta.span)
+                            }
+                            Trap::Overflow => format!(
+                                "Overflow dividing {} by {} as {} at {}",
+                                left.value, right.value, left.datatype, bin_op.meta.span
+                            ),
+                        })
+                    })?;
+                Value::new(left.datatype, value)
+            }
+            Expression::LiteralExpression(literal, _) => Value::new(
+                literal.datatype(),
+                literal.value().parse().expect("literals are range checked"),
+            ),
+            Expression::VariableReferenceExpression(name, _) => *frame.lookup(name),
+            Expression::FunctionCallExpression(call) => {
+                let mut arguments = Vec::new();
+                for argument in &call.parameters {
+                    arguments.push(self.value(frame, argument)?);
+                }
+                return self.call(&call.name, arguments, call.meta.span);
+            }
+            Expression::CastExpression(cast) => {
+                let Type::SimpleType(datatype) = cast.datatype;
+                Value::new(datatype, self.value(frame, &cast.value)?.value)
+            }
+        };
+        Ok(Some(value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use analysis::entry::EntryCheck;
+    use lexer::Lexer;
+    use parser::Parser;
+    use typeck::TypeCheck;
+
+    fn run(source: &str) -> Result<i32, String> {
+        run_with_input(source, "").0
+    }
+
+    // Exit status and everything the program printed
+    fn run_with_input(source: &str, input: &str) -> (Result<i32, String>, String) {
+        let parser = Parser::new(Lexer::tokenise_source(source));
+        assert_eq!(parser.get_errors(), &Vec::<String>::new());
+        let mut program = parser.get_program().unwrap().clone();
+        let checker = TypeCheck::new(&program);
+        assert_eq!(checker.symbols().get_errors(), &Vec::<String>::new());
+        assert_eq!(checker.get_errors(), &Vec::<String>::new());
+        checker.rewrite_literals(&mut program);
+
+        let entry = *EntryCheck::new(&program).entry().unwrap();
+        let mut output = Vec::new();
+        let status =
+            Interpreter::with_io(&program, Box::new(input.as_bytes()), Box::new(&mut output))
+                .run(&entry, 1);
+        (status, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn recursion_and_loops() {
+        assert_eq!(
+            run(
+                "fn fact(n: u32) -> u32 { if n { return n * fact(n - 1); } return 1; }\n\
+                 fn fib(n: u8) -> u8 {\n\
+                 let a: u8 = 0; let b: u8 = 1;\n\
+                 while n { let c: u8 = a + b; a = b; b = c; n = n - 1; }\n\
+                 return a;\n\
+                 }\n\
+                 fn main() -> u32 { return fact(5) + fib(10) as u32; }"
+            ),
+            Ok(175)
+        );
+    }
+
+    #[test]
+    fn blocks_shadow_and_restore() {
+        assert_eq!(
+            run("fn main() -> i32 {\n\
+                 let a: i32 = 1;\n\
+                 if a { let a: i32 = 10; a = a + 1; } else if a - 1 { a = 3; }\n\
+                 while a - 4 { let b: i32 = a; a = b + 1; }\n\
+                 return a;\n\
+                 }"),
+            Ok(4)
+        );
+    }
+
+    #[test]
+    fn arithmetic_wraps_per_type() {
+        assert_eq!(
+            run("fn main() -> i64 {\n\
+                 let a: u8 = 200; let b: u8 = a + a;\n\
+                 let c: i8 = 127; c = c + 1;\n\
+                 let d: u16 = 0; d = d - 1;\n\
+                 return b as i64 + c as i64 + d as i64;\n\
+                 }"),
+            Ok(144 - 128 + 65535)
+        );
+        assert_eq!(
+            run("fn main() -> i8 { let a: i16 = 300; return a as i8 - 7 / 2 + (0 - 7) % 2; }"),
+            Ok(44 - 3 - 1)
+        );
+    }
+
+    #[test]
+    fn traps() {
+        assert_eq!(
+            run("fn main(argc: u8) -> u8 { return 10 / (argc - 1); }"),
+            Err(String::from("Division by zero at 0:36"))
+        );
+        assert_eq!(
+            run("fn main() -> i8 { let a: i8 = 0 - 127 - 1; return a % (0 - 1); }"),
+            Err(String::from("Overflow dividing -128 by -1 as i8 at 0:52"))
+        );
+        assert_eq!(
+            run("fn f(n: u64) -> u64 { return f(n + 1); }\nfn main() -> void { f(0); }"),
+            Err(String::from("Stack overflow calling \"f\" at 0:3"))
+        );
+    }
+
+    #[test]
+    fn exit_status_and_arguments() {
+        assert_eq!(run("fn main() -> void {}"), Ok(0));
+        assert_eq!(run("fn main(argc: i32) -> i32 { return argc * 3; }"), Ok(3));
+        assert_eq!(run("fn main() -> u64 { return 4294967298; }"), Ok(2));
+    }
+
+    #[test]
+    fn builtins_print_and_read() {
+        assert_eq!(
+            run_with_input(
+                "fn main() -> void {\n\
+                 let a: i64 = read_int(); let b: i64 = read_int();\n\
+                 print_int(a - b);\n\
+                 print_uint(0 as u64 - 1);\n\
+                 print_char(104); print_char(105); print_char(10);\n\
+                 }",
+                "3\n  5 \n"
+            ),
+            (Ok(0), String::from("-2\n18446744073709551615\nhi\n"))
+        );
+        assert_eq!(
+            run_with_input("fn main() -> i64 { return read_int(); }", "x1\n"),
+            (
+                Err(String::from(
+                    "read_int at 0:26 expected an integer but read \"x1\""
+                )),
+                String::new()
+            )
+        );
+        assert_eq!(
+            run_with_input("fn main() -> i64 { return read_int(); }", ""),
+            (
+                Err(String::from(
+                    "read_int at 0:26 reached the end of the input"
+                )),
+                String::new()
+            )
+        );
+    }
+
+    #[test]
+    fn exit_ends_the_program() {
+        assert_eq!(
+            run_with_input(
+                "fn f(n: i32) -> void { print_int(n as i64); if n { exit(n); } }\n\
+                 fn main() -> i32 { f(0); f(7); f(8); return 1; }",
+                ""
+            ),
+            (Ok(7), String::from("0\n7\n"))
+        );
+    }
+}
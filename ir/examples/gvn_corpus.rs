@@ -0,0 +1,99 @@
+// Counts the instructions left in every program of a corpus with and without GVN:
+//
+//     cargo run -p ir --example gvn_corpus [directory]
+//
+// The directory defaults to test_files/corpus, its files are compiled in name order.
+
+use std::{env, fs, path::PathBuf};
+
+use ir::{Module, dce::dce, gvn::gvn, lower::lower_program, sccp::sccp, ssa::into_ssa};
+use lexer::Lexer;
+use parser::Parser;
+use typeck::TypeCheck;
+
+fn instructions(module: &Module) -> usize {
+    module
+        .functions
+        .iter()
+        .flat_map(|function| &function.blocks)
+        .map(|block| block.instructions.len())
+        .sum()
+}
+
+// The instruction counts after the passes of the pipeline, without GVN and with it
+fn measure(source: &str) -> Result<(usize, usize), String> {
+    let parser = Parser::new(Lexer::tokenise_source(source));
+    let Some(program) = parser.get_program() else {
+        return Err(parser.get_errors().join("\n"));
+    };
+    let mut program = program.clone();
+    let checker = TypeCheck::new(&program);
+    if !checker.get_errors().is_empty() {
+        return Err(checker.get_errors().join("\n"));
+    }
+    checker.rewrite_literals(&mut program);
+
+    let mut module = lower_program(&program);
+    for function in &mut module.functions {
+        into_ssa(function);
+        let errors = sccp(function);
+        if !errors.is_empty() {
+            return Err(errors.join("\n"));
+        }
+    }
+    let mut without = module.clone();
+    for function in &mut without.functions {
+        dce(function);
+    }
+    for function in &mut module.functions {
+        gvn(function);
+        dce(function);
+    }
+    Ok((instructions(&without), instructions(&module)))
+}
+
+fn main() {
+    let directory = env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test_files/corpus"));
+    let mut paths: Vec<PathBuf> = fs::read_dir(&directory)
+        .unwrap_or_else(|error| panic!("cannot read {}: {}", directory.display(), error))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+
+    println!(
+        "{:<24} {:>8} {:>8} {:>8}",
+        "program", "without", "with", "saved"
+    );
+    let (mut total_without, mut total_with) = (0, 0);
+    for path in paths {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let source = fs::read_to_string(&path).unwrap();
+        match measure(&source) {
+            Ok((without, with)) => {
+                println!(
+                    "{:<24} {:>8} {:>8} {:>8}",
+                    name,
+                    without,
+                    with,
+                    without - with
+                );
+                total_without += without;
+                total_with += with;
+            }
+            Err(error) => eprintln!("{}: {}", name, error),
+        }
+    }
+    let saved = total_without - total_with;
+    println!(
+        "{:<24} {:>8} {:>8} {:>8} ({:.1}%)",
+        "total",
+        total_without,
+        total_with,
+        saved,
+        100.0 * saved as f64 / total_without.max(1) as f64
+    );
+}
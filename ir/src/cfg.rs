@@ -0,0 +1,334 @@
+use crate::{Block, BlockId, Function, Instruction, Module, Terminator};
+
+// Control-flow graph of one function, built from the terminators of its blocks. Edges are kept
+// once per terminator target, so a block lists a predecessor once for every edge from it.
+// Changing the blocks of the function makes the graph stale, it has to be built again.
+pub struct Cfg {
+    successors: Vec<Vec<BlockId>>,
+    predecessors: Vec<Vec<BlockId>>,
+    // Blocks reachable from the entry block only
+    reverse_postorder: Vec<BlockId>,
+}
+
+impl Cfg {
+    pub fn new(function: &Function) -> Self {
+        let successors: Vec<Vec<BlockId>> = function
+            .blocks
+            .iter()
+            .map(|block| block.terminator.successors())
+            .collect();
+        let mut predecessors = vec![Vec::new(); function.blocks.len()];
+        for (i, targets) in successors.iter().enumerate() {
+            for target in targets {
+                predecessors[target.0 as usize].push(BlockId(i as u32));
+            }
+        }
+
+        // Depth first from the entry, a block is finished once all its successors are
+        let mut postorder = Vec::new();
+        let mut visited = vec![false; function.blocks.len()];
+        if !function.blocks.is_empty() {
+            let mut stack = vec![(BlockId(0), 0)];
+            visited[0] = true;
+            while let Some((block, next)) = stack.last_mut() {
+                match successors[block.0 as usize].get(*next) {
+                    Some(successor) => {
+                        *next += 1;
+                        if !visited[successor.0 as usize] {
+                            visited[successor.0 as usize] = true;
+                            stack.push((*successor, 0));
+                        }
+                    }
+                    None => {
+                        postorder.push(*block);
+                        stack.pop();
+                    }
+                }
+            }
+        }
+        postorder.reverse();
+
+        Self {
+            successors,
+            predecessors,
+            reverse_postorder: postorder,
+        }
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.successors.len()
+    }
+
+    pub fn successors(&self, block: BlockId) -> &[BlockId] {
+        &self.successors[block.0 as usize]
+    }
+
+    pub fn predecessors(&self, block: BlockId) -> &[BlockId] {
+        &self.predecessors[block.0 as usize]
+    }
+
+    // Every block comes before its successors except along back edges, starting with the entry
+    pub fn reverse_postorder(&self) -> &[BlockId] {
+        &self.reverse_postorder
+    }
+
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        self.reverse_postorder.contains(&block)
+    }
+}
+
+// Puts a new block on every edge from a block with several successors to a block with several
+// predecessors, so code can be placed on the edge without running on any other path. The new
+// blocks are added after the existing ones and take the place of the split block in the phis of
+// the target. Returns how many edges were split.
+pub fn split_critical_edges(function: &mut Function) -> usize {
+    let cfg = Cfg::new(function);
+    let mut split = 0;
+    for i in 0..function.blocks.len() {
+        let block = BlockId(i as u32);
+        if cfg.successors(block).len() < 2 {
+            continue;
+        }
+        let Terminator::CondBr {
+            then_block,
+            else_block,
+            ..
+        } = function.block(block).terminator.clone()
+        else {
+            continue;
+        };
+        let mut targets = [then_block, else_block];
+        for target in &mut targets {
+            if cfg.predecessors(*target).len() > 1 {
+                function.blocks.push(Block {
+                    instructions: Vec::new(),
+                    terminator: Terminator::Br(*target),
+                });
+                let edge = BlockId(function.blocks.len() as u32 - 1);
+                for instruction in &mut function.block_mut(*target).instructions {
+                    if let Instruction::Phi { incoming, .. } = instruction
+                        && let Some(entry) = incoming.iter_mut().find(|(from, _)| *from == block)
+                    {
+                        entry.0 = edge;
+                    }
+                }
+                *target = edge;
+                split += 1;
+            }
+        }
+        if let Terminator::CondBr {
+            then_block,
+            else_block,
+            ..
+        } = &mut function.block_mut(block).terminator
+        {
+            [*then_block, *else_block] = targets;
+        }
+    }
+    split
+}
+
+// Drops the blocks that cannot be reached from the entry block, together with their entries in
+// phis, and numbers the rest in their old order. Returns how many blocks were removed.
+pub fn remove_unreachable_blocks(function: &mut Function) -> usize {
+    let cfg = Cfg::new(function);
+    let mut numbers = vec![None; function.blocks.len()];
+    let mut next = 0;
+    for (i, number) in numbers.iter_mut().enumerate() {
+        if cfg.is_reachable(BlockId(i as u32)) {
+            *number = Some(BlockId(next));
+            next += 1;
+        }
+    }
+    let blocks = std::mem::take(&mut function.blocks);
+    for (mut block, number) in blocks.into_iter().zip(&numbers) {
+        if number.is_none() {
+            continue;
+        }
+        for target in block.terminator.targets_mut() {
+            *target = numbers[target.0 as usize].unwrap();
+        }
+        for instruction in &mut block.instructions {
+            if let Instruction::Phi { incoming, .. } = instruction {
+                incoming.retain(|(from, _)| numbers[from.0 as usize].is_some());
+                for (from, _) in incoming {
+                    *from = numbers[from.0 as usize].unwrap();
+                }
+            }
+        }
+        function.blocks.push(block);
+    }
+    numbers.len() - next as usize
+}
+
+// Renders every function as a cluster of blocks listing their instructions, with the edges
+// of a cond_br labelled true and false
+pub fn module_to_dot(module: &Module) -> String {
+    let mut out = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+    for (i, function) in module.functions.iter().enumerate() {
+        out.push_str(&format!("    subgraph cluster_{} {{\n", i));
+        out.push_str(&format!(
+            "        label=\"fn {}\";\n",
+            escape(&function.name)
+        ));
+        for (j, block) in function.blocks.iter().enumerate() {
+            let id = BlockId(j as u32);
+            let mut label = format!("{}:\n", id);
+            for instruction in &block.instructions {
+                label.push_str(&format!("    {}\n", function.display(instruction)));
+            }
+            label.push_str(&format!("    {}\n", block.terminator));
+            out.push_str(&format!(
+                "        f{}_{} [label=\"{}\"];\n",
+                i,
+                id,
+                escape(&label)
+            ));
+        }
+        for (j, block) in function.blocks.iter().enumerate() {
+            let edges: Vec<(BlockId, &str)> = match &block.terminator {
+                Terminator::CondBr {
+                    then_block,
+                    else_block,
+                    ..
+                } => vec![(*then_block, "true"), (*else_block, "false")],
+                terminator => terminator
+                    .successors()
+                    .into_iter()
+                    .map(|target| (target, ""))
+                    .collect(),
+            };
+            for (target, label) in edges {
+                out.push_str(&format!("        f{}_bb{} -> f{}_{}", i, j, i, target));
+                if !label.is_empty() {
+                    out.push_str(&format!(" [label=\"{}\"]", label));
+                }
+                out.push_str(";\n");
+            }
+        }
+        out.push_str("    }\n");
+    }
+    out.push_str("}\n");
+    out
+}
+
+// Lines of a label are left aligned
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\l")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Operand, Reg, lower_source};
+
+    const LOOP: &str = "fn f(a: i32) -> i32 {\n\
+                        while a { if a - 5 { a = a - 1; } else { return 5; } }\n\
+                        return a;\n\
+                        }";
+
+    #[test]
+    fn edges_and_order() {
+        let module = lower_source(LOOP);
+        let cfg = Cfg::new(&module.functions[0]);
+        let blocks = |ids: &[u32]| ids.iter().map(|id| BlockId(*id)).collect::<Vec<_>>();
+        // bb1 is the loop header, bb4 the then branch and bb6 the join jumping back
+        assert_eq!(cfg.successors(BlockId(1)), blocks(&[2, 3]));
+        assert_eq!(cfg.predecessors(BlockId(1)), blocks(&[0, 6]));
+        assert_eq!(cfg.predecessors(BlockId(6)), blocks(&[4]));
+        assert_eq!(cfg.reverse_postorder(), blocks(&[0, 1, 3, 2, 5, 4, 6]));
+        assert!(cfg.is_reachable(BlockId(5)));
+    }
+
+    #[test]
+    fn unreachable_blocks_are_not_ordered() {
+        let mut function = lower_source("fn f() -> void {}").functions.remove(0);
+        function.blocks.push(Block {
+            instructions: Vec::new(),
+            terminator: Terminator::Br(BlockId(0)),
+        });
+        let cfg = Cfg::new(&function);
+        assert_eq!(cfg.reverse_postorder(), [BlockId(0)]);
+        assert!(!cfg.is_reachable(BlockId(1)));
+        assert_eq!(cfg.predecessors(BlockId(0)), [BlockId(1)]);
+    }
+
+    #[test]
+    fn critical_edges_are_split() {
+        // Lowering gives every branch a block of its own, critical edges only show up once
+        // blocks are merged, like here where both targets of each cond_br have two predecessors
+        let mut function = lower_source("fn f(a: u8) -> u8 { return a; }")
+            .functions
+            .remove(0);
+        let cond_br = |then_block, else_block| Terminator::CondBr {
+            condition: Operand::Reg(Reg(0)),
+            then_block: BlockId(then_block),
+            else_block: BlockId(else_block),
+        };
+        let ret = function.blocks[0].terminator.clone();
+        function.blocks = vec![
+            Block {
+                instructions: Vec::new(),
+                terminator: cond_br(1, 2),
+            },
+            Block {
+                instructions: Vec::new(),
+                terminator: cond_br(1, 2),
+            },
+            Block {
+                instructions: Vec::new(),
+                terminator: ret,
+            },
+        ];
+        assert_eq!(split_critical_edges(&mut function), 4);
+        assert_eq!(
+            function.to_string(),
+            "fn f(%0: u8) -> u8 {\n\
+             bb0:\n    \
+             cond_br %0, bb3, bb4\n\
+             bb1:\n    \
+             cond_br %0, bb5, bb6\n\
+             bb2:\n    \
+             ret %0\n\
+             bb3:\n    \
+             br bb1\n\
+             bb4:\n    \
+             br bb2\n\
+             bb5:\n    \
+             br bb1\n\
+             bb6:\n    \
+             br bb2\n\
+             }\n"
+        );
+        assert_eq!(split_critical_edges(&mut function), 0);
+        assert_eq!(
+            split_critical_edges(&mut lower_source(LOOP).functions[0]),
+            0
+        );
+    }
+
+    #[test]
+    fn dot_output() {
+        assert_eq!(
+            module_to_dot(&lower_source(
+                "fn f(a: u8) -> u8 { if a { return 1; } return a; }"
+            )),
+            "digraph cfg {\n    \
+             node [shape=box, fontname=\"monospace\"];\n    \
+             subgraph cluster_0 {\n        \
+             label=\"fn f\";\n        \
+             f0_bb0 [label=\"bb0:\\l    cond_br %0, bb1, bb2\\l\"];\n        \
+             f0_bb1 [label=\"bb1:\\l    ret 1\\l\"];\n        \
+             f0_bb2 [label=\"bb2:\\l    br bb3\\l\"];\n        \
+             f0_bb3 [label=\"bb3:\\l    ret %0\\l\"];\n        \
+             f0_bb0 -> f0_bb1 [label=\"true\"];\n        \
+             f0_bb0 -> f0_bb2 [label=\"false\"];\n        \
+             f0_bb2 -> f0_bb3;\n    \
+             }\n\
+             }\n"
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{
+    BlockId, Function, Instruction, Operand, Reg, Terminator,
+    cfg::{Cfg, remove_unreachable_blocks},
+};
+
+// Dead code elimination, in or out of SSA form. Blocks that cannot be reached are dropped, then
+// instructions are marked live starting from the terminators and the instructions with side
+// effects, following the definitions of every register a live instruction uses. Whatever is
+// left unmarked is removed, which also takes out cycles of phis that only feed each other.
+// Finally a block that jumps to a block with no other predecessor takes over its instructions
+// and terminator, so chains of blocks left behind by SCCP become one.
+pub fn dce(function: &mut Function) {
+    remove_unreachable_blocks(function);
+    sweep(function);
+    merge_chains(function);
+}
+
+fn sweep(function: &mut Function) {
+    // Out of SSA form a register can have several definitions, a live register keeps them all
+    let mut definitions: Vec<Vec<(usize, usize)>> = vec![Vec::new(); function.registers.len()];
+    let mut live = vec![false; function.registers.len()];
+    let mut worklist: Vec<Reg> = Vec::new();
+    let mark = |operand: Operand, live: &mut Vec<bool>, worklist: &mut Vec<Reg>| {
+        if let Operand::Reg(reg) = operand
+            && !live[reg.0 as usize]
+        {
+            live[reg.0 as usize] = true;
+            worklist.push(reg);
+        }
+    };
+    for (i, block) in function.blocks.iter().enumerate() {
+        for (j, instruction) in block.instructions.iter().enumerate() {
+            if let Some(dest) = instruction.dest() {
+                definitions[dest.0 as usize].push((i, j));
+            }
+            if instruction.has_side_effects() {
+                for operand in instruction.operands() {
+                    mark(operand, &mut live, &mut worklist);
+                }
+            }
+        }
+        for operand in block.terminator.operands() {
+            mark(operand, &mut live, &mut worklist);
+        }
+    }
+    while let Some(reg) = worklist.pop() {
+        for (i, j) in &definitions[reg.0 as usize] {
+            for operand in function.blocks[*i].instructions[*j].operands() {
+                mark(operand, &mut live, &mut worklist);
+            }
+        }
+    }
+
+    for block in &mut function.blocks {
+        block.instructions.retain(|instruction| {
+            instruction.has_side_effects()
+                || instruction.dest().is_some_and(|dest| live[dest.0 as usize])
+        });
+    }
+}
+
+// Unreachable blocks have to be gone already, so the entry is the only block without
+// predecessors and cannot be merged into another
+fn merge_chains(function: &mut Function) {
+    let cfg = Cfg::new(function);
+    let mut merged = vec![false; function.blocks.len()];
+    for i in 0..function.blocks.len() {
+        let block = BlockId(i as u32);
+        if merged[i] {
+            continue;
+        }
+        // Taking over the terminator of the next block gives the edges out of it to this block
+        // without changing how many predecessors their targets have
+        while let Terminator::Br(next) = function.block(block).terminator
+            && next != block
+            && cfg.predecessors(next).len() == 1
+        {
+            merged[next.0 as usize] = true;
+            let taken = std::mem::replace(
+                &mut function.block_mut(next).terminator,
+                Terminator::Unreachable,
+            );
+            let mut instructions = std::mem::take(&mut function.block_mut(next).instructions);
+            // With one predecessor a phi has one entry, so its value can be used directly
+            let phis: Vec<(Reg, Operand)> = instructions
+                .iter()
+                .filter_map(|instruction| match instruction {
+                    Instruction::Phi { dest, incoming } => Some((*dest, incoming[0].1)),
+                    _ => None,
+                })
+                .collect();
+            instructions.retain(|instruction| !instruction.is_phi());
+            for target in taken.successors() {
+                for instruction in &mut function.block_mut(target).instructions {
+                    if let Instruction::Phi { incoming, .. } = instruction {
+                        for (from, _) in incoming {
+                            if *from == next {
+                                *from = block;
+                            }
+                        }
+                    }
+                }
+            }
+            let current = function.block_mut(block);
+            current.instructions.append(&mut instructions);
+            current.terminator = taken;
+            for (reg, value) in phis {
+                replace(function, reg, value);
+            }
+        }
+    }
+    remove_unreachable_blocks(function);
+}
+
+fn replace(function: &mut Function, reg: Reg, value: Operand) {
+    for block in &mut function.blocks {
+        let operands = block
+            .instructions
+            .iter_mut()
+            .flat_map(|instruction| instruction.operands_mut())
+            .chain(block.terminator.operands_mut());
+        for operand in operands {
+            if *operand == Operand::Reg(reg) {
+                *operand = value;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lower_source, sccp::sccp, ssa::into_ssa, verify::Verifier};
+    use std::fs;
+
+    // Checks the IR of test_files/dce/name.c before and after DCE against name.ir. Running the
+    // tests with UPDATE_SNAPSHOTS set writes the snapshots instead
+    fn snapshot(name: &str) {
+        let source = fs::read_to_string(format!("test_files/dce/{}.c", name)).unwrap();
+        let mut module = lower_source(&source);
+        for function in &mut module.functions {
+            into_ssa(function);
+            assert_eq!(sccp(function), Vec::<String>::new());
+        }
+        let before = module.to_string();
+        for function in &mut module.functions {
+            dce(function);
+        }
+        assert_eq!(
+            Verifier::new(&module, true).get_errors(),
+            &Vec::<String>::new()
+        );
+        let actual = format!("; before\n{}\n; after\n{}", before, module);
+
+        let path = format!("test_files/dce/{}.ir", name);
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            fs::write(&path, actual).unwrap();
+        } else {
+            assert_eq!(actual, fs::read_to_string(&path).unwrap());
+        }
+    }
+
+    #[test]
+    fn folded_branches_become_one_block() {
+        snapshot("folded_branches");
+    }
+
+    #[test]
+    fn dead_loop_values() {
+        snapshot("dead_loop");
+    }
+
+    #[test]
+    fn side_effects_are_kept() {
+        snapshot("side_effects");
+    }
+
+    #[test]
+    fn non_ssa_registers_keep_every_definition() {
+        // c only feeds itself, b is returned after one of its two definitions
+        let mut module = lower_source(
+            "fn f(a: u8) -> u8 {\n\
+             let b: u8 = a * 2; let c: u8 = b + a;\n\
+             while a { b = 1; c = c + 1; a = a - 1; }\n\
+             return b;\n\
+             }",
+        );
+        dce(&mut module.functions[0]);
+        assert_eq!(
+            Verifier::new(&module, false).get_errors(),
+            &Vec::<String>::new()
+        );
+        assert_eq!(
+            module.to_string(),
+            "fn f(%0: u8) -> u8 {\n\
+             bb0:\n    \
+             %1: u8 = mul %0, 2\n    \
+             %2: u8 = copy %1\n    \
+             br bb1\n\
+             bb1:\n    \
+             cond_br %0, bb2, bb3\n\
+             bb2:\n    \
+             %2: u8 = copy 1\n    \
+             %6: u8 = sub %0, 1\n    \
+             %0: u8 = copy %6\n    \
+             br bb1\n\
+             bb3:\n    \
+             ret %2\n\
+             }\n"
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use crate::{BlockId, cfg::Cfg};
+
+// Dominator tree and dominance frontiers, computed with the iterative algorithm of Cooper,
+// Harvey and Kennedy. A block dominates another when every path from the entry to the other
+// goes through it. Blocks that cannot be reached have no dominators and dominate nothing.
+pub struct Dominators {
+    // The entry block is its own immediate dominator here, None for unreachable blocks
+    idoms: Vec<Option<BlockId>>,
+    children: Vec<Vec<BlockId>>,
+    frontiers: Vec<Vec<BlockId>>,
+}
+
+impl Dominators {
+    pub fn new(cfg: &Cfg) -> Self {
+        let count = cfg.block_count();
+        let order = cfg.reverse_postorder();
+        let mut position = vec![usize::MAX; count];
+        for (i, block) in order.iter().enumerate() {
+            position[block.0 as usize] = i;
+        }
+
+        let mut idoms: Vec<Option<BlockId>> = vec![None; count];
+        if let Some(entry) = order.first() {
+            idoms[entry.0 as usize] = Some(*entry);
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in order.iter().skip(1) {
+                let mut idom = None;
+                for predecessor in cfg.predecessors(*block) {
+                    if idoms[predecessor.0 as usize].is_none() {
+                        continue;
+                    }
+                    idom = Some(match idom {
+                        None => *predecessor,
+                        Some(idom) => intersect(&idoms, &position, *predecessor, idom),
+                    });
+                }
+                if idoms[block.0 as usize] != idom {
+                    idoms[block.0 as usize] = idom;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut children = vec![Vec::new(); count];
+        for block in order.iter().skip(1) {
+            let idom = idoms[block.0 as usize].unwrap();
+            children[idom.0 as usize].push(*block);
+        }
+
+        // A join is in the frontier of every block on the way up from each of its
+        // predecessors to its immediate dominator. The entry counts as a join if it has any
+        // predecessor since control also enters it from outside
+        let idom = |block: BlockId| idoms[block.0 as usize].filter(|idom| *idom != block);
+        let mut frontiers: Vec<Vec<BlockId>> = vec![Vec::new(); count];
+        for block in order {
+            let predecessors = cfg.predecessors(*block);
+            let entries = predecessors.len() + usize::from(*block == order[0]);
+            if entries < 2 {
+                continue;
+            }
+            for predecessor in predecessors {
+                if idoms[predecessor.0 as usize].is_none() {
+                    continue;
+                }
+                let mut runner = Some(*predecessor);
+                while let Some(current) = runner
+                    && runner != idom(*block)
+                {
+                    let frontier = &mut frontiers[current.0 as usize];
+                    if !frontier.contains(block) {
+                        frontier.push(*block);
+                    }
+                    runner = idom(current);
+                }
+            }
+        }
+
+        Self {
+            idoms,
+            children,
+            frontiers,
+        }
+    }
+
+    // None for the entry block and unreachable blocks
+    pub fn idom(&self, block: BlockId) -> Option<BlockId> {
+        self.idoms[block.0 as usize].filter(|idom| *idom != block)
+    }
+
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        self.idoms[block.0 as usize].is_some()
+    }
+
+    // Every block dominates itself
+    pub fn dominates(&self, dominator: BlockId, block: BlockId) -> bool {
+        if self.idoms[block.0 as usize].is_none() {
+            return false;
+        }
+        let mut runner = block;
+        loop {
+            if runner == dominator {
+                return true;
+            }
+            match self.idom(runner) {
+                Some(idom) => runner = idom,
+                None => return false,
+            }
+        }
+    }
+
+    // Blocks immediately dominated by block, in reverse postorder
+    pub fn children(&self, block: BlockId) -> &[BlockId] {
+        &self.children[block.0 as usize]
+    }
+
+    // Blocks that block does not strictly dominate but dominates a predecessor of
+    pub fn frontier(&self, block: BlockId) -> &[BlockId] {
+        &self.frontiers[block.0 as usize]
+    }
+}
+
+fn intersect(idoms: &[Option<BlockId>], position: &[usize], a: BlockId, b: BlockId) -> BlockId {
+    let (mut a, mut b) = (a, b);
+    while a != b {
+        while position[a.0 as usize] > position[b.0 as usize] {
+            a = idoms[a.0 as usize].unwrap();
+        }
+        while position[b.0 as usize] > position[a.0 as usize] {
+            b = idoms[b.0 as usize].unwrap();
+        }
+    }
+    a
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Block, Function, Operand, Reg, Terminator};
+    use ast::SimpleType;
+
+    // Blocks with the given terminators and no instructions, conditions test %0
+    fn function(terminators: Vec<Terminator>) -> Function {
+        Function {
+            name: String::from("f"),
+            parameters: vec![Reg(0)],
+            return_type: SimpleType::Void,
+            registers: vec![SimpleType::I64],
+            blocks: terminators
+                .into_iter()
+                .map(|terminator| Block {
+                    instructions: Vec::new(),
+                    terminator,
+                })
+                .collect(),
+        }
+    }
+
+    fn br(target: u32) -> Terminator {
+        Terminator::Br(BlockId(target))
+    }
+
+    fn cond_br(then_block: u32, else_block: u32) -> Terminator {
+        Terminator::CondBr {
+            condition: Operand::Reg(Reg(0)),
+            then_block: BlockId(then_block),
+            else_block: BlockId(else_block),
+        }
+    }
+
+    fn blocks(ids: &[u32]) -> Vec<BlockId> {
+        ids.iter().map(|id| BlockId(*id)).collect()
+    }
+
+    #[test]
+    fn diamond_inside_a_loop() {
+        // bb1 is a loop header, bb2 and bb3 the branches of an if joining in bb4
+        let function = function(vec![
+            br(1),
+            cond_br(2, 5),
+            cond_br(3, 4),
+            br(4),
+            br(1),
+            Terminator::Ret(None),
+            br(5),
+        ]);
+        let dominators = Dominators::new(&Cfg::new(&function));
+        let idoms: Vec<Option<BlockId>> = (0..7).map(|i| dominators.idom(BlockId(i))).collect();
+        assert_eq!(
+            idoms,
+            vec![
+                None,
+                Some(BlockId(0)),
+                Some(BlockId(1)),
+                Some(BlockId(2)),
+                Some(BlockId(2)),
+                Some(BlockId(1)),
+                None
+            ]
+        );
+        assert_eq!(dominators.children(BlockId(2)), blocks(&[3, 4]));
+        assert!(dominators.dominates(BlockId(1), BlockId(4)));
+        assert!(dominators.dominates(BlockId(4), BlockId(4)));
+        assert!(!dominators.dominates(BlockId(3), BlockId(4)));
+        assert!(!dominators.dominates(BlockId(6), BlockId(5)));
+
+        assert_eq!(dominators.frontier(BlockId(3)), blocks(&[4]));
+        assert_eq!(dominators.frontier(BlockId(4)), blocks(&[1]));
+        assert_eq!(dominators.frontier(BlockId(2)), blocks(&[1]));
+        assert_eq!(dominators.frontier(BlockId(1)), blocks(&[1]));
+        assert_eq!(dominators.frontier(BlockId(0)), blocks(&[]));
+        assert_eq!(dominators.frontier(BlockId(5)), blocks(&[]));
+    }
+
+    #[test]
+    fn self_loop() {
+        let function = function(vec![cond_br(0, 1), Terminator::Ret(None)]);
+        let dominators = Dominators::new(&Cfg::new(&function));
+        assert_eq!(dominators.idom(BlockId(1)), Some(BlockId(0)));
+        assert_eq!(dominators.frontier(BlockId(0)), blocks(&[0]));
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use ast::{Operator, SimpleType};
+
+use crate::{BlockId, Function, Instruction, Operand, Reg, cfg::Cfg, dominators::Dominators};
+
+// Dominator based global value numbering over a function in SSA form. Walking the dominator
+// tree, an instruction that computes the same expression as one in a dominating block is
+// dropped and its register replaced by the earlier one, which already holds the value on every
+// path that reaches it. Copies are replaced by what they copy and a phi that only ever takes
+// one value by that value. Additions and multiplications match with their operands swapped.
+//
+// Calls may have side effects, so they are never numbered and each keeps its own result. A
+// division is numbered like any other arithmetic: if the dominating one did not trap this one
+// cannot either. The IR has no memory, so there are no loads to deduplicate.
+pub fn gvn(function: &mut Function) {
+    let cfg = Cfg::new(function);
+    let dominators = Dominators::new(&cfg);
+    let mut numbering = Numbering {
+        dominators: &dominators,
+        values: HashMap::new(),
+        replacements: vec![None; function.registers.len()],
+    };
+    numbering.block(function, BlockId(0));
+
+    let Numbering { replacements, .. } = numbering;
+    let resolve = |operand: Operand| resolve(&replacements, operand);
+    for block in &mut function.blocks {
+        block.instructions.retain(|instruction| {
+            instruction
+                .dest()
+                .is_none_or(|dest| replacements[dest.0 as usize].is_none())
+        });
+        let operands = block
+            .instructions
+            .iter_mut()
+            .flat_map(|instruction| instruction.operands_mut())
+            .chain(block.terminator.operands_mut());
+        for operand in operands {
+            *operand = resolve(*operand);
+        }
+    }
+}
+
+// What an instruction computes, with its operands already numbered
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Expression {
+    Binary(Operator, Operand, Operand, SimpleType),
+    Cast(SimpleType, Operand, SimpleType),
+    // Phis only compute the same value as a phi in the same block
+    Phi(BlockId, Vec<(BlockId, Operand)>),
+}
+
+struct Numbering<'a> {
+    dominators: &'a Dominators,
+    // Expressions computed in the dominating blocks of the current one
+    values: HashMap<Expression, Reg>,
+    // The operand holding the value of each register that is dropped
+    replacements: Vec<Option<Operand>>,
+}
+
+impl Numbering<'_> {
+    fn block(&mut self, function: &Function, block: BlockId) {
+        let mut added = Vec::new();
+        for instruction in &function.block(block).instructions {
+            let Some(dest) = instruction.dest() else {
+                continue;
+            };
+            let expression = match instruction {
+                Instruction::Copy { value, .. } => {
+                    self.replacements[dest.0 as usize] = Some(self.resolve(*value));
+                    continue;
+                }
+                Instruction::Binary {
+                    op, left, right, ..
+                } => {
+                    let (mut left, mut right) = (self.resolve(*left), self.resolve(*right));
+                    if matches!(op, Operator::Plus | Operator::Mul) && order(right) < order(left) {
+                        (left, right) = (right, left);
+                    }
+                    Expression::Binary(*op, left, right, function.register_type(dest))
+                }
+                Instruction::Cast { from, value, .. } => {
+                    Expression::Cast(*from, self.resolve(*value), function.register_type(dest))
+                }
+                Instruction::Call { .. } => continue,
+                Instruction::Phi { incoming, .. } => {
+                    let incoming: Vec<(BlockId, Operand)> = incoming
+                        .iter()
+                        .map(|(from, value)| (*from, self.resolve(*value)))
+                        .collect();
+                    // Entries that are the phi itself come around a loop that does not change it
+                    let mut values = incoming
+                        .iter()
+                        .map(|(_, value)| *value)
+                        .filter(|value| *value != Operand::Reg(dest));
+                    if let Some(first) = values.next()
+                        && values.all(|value| value == first)
+                    {
+                        self.replacements[dest.0 as usize] = Some(first);
+                        continue;
+                    }
+                    Expression::Phi(block, incoming)
+                }
+            };
+            match self.values.get(&expression) {
+                Some(reg) => self.replacements[dest.0 as usize] = Some(Operand::Reg(*reg)),
+                None => {
+                    self.values.insert(expression.clone(), dest);
+                    added.push(expression);
+                }
+            }
+        }
+
+        for child in self.dominators.children(block) {
+            self.block(function, *child);
+        }
+        for expression in added {
+            self.values.remove(&expression);
+        }
+    }
+
+    fn resolve(&self, operand: Operand) -> Operand {
+        resolve(&self.replacements, operand)
+    }
+}
+
+// Replacements can be chained, a copy of a register that was itself dropped
+fn resolve(replacements: &[Option<Operand>], mut operand: Operand) -> Operand {
+    while let Operand::Reg(reg) = operand
+        && let Some(replacement) = replacements[reg.0 as usize]
+    {
+        operand = replacement;
+    }
+    operand
+}
+
+// Puts the operands of a commutative operator in one order, constants last
+fn order(operand: Operand) -> (u8, i128) {
+    match operand {
+        Operand::Reg(reg) => (0, reg.0 as i128),
+        Operand::Const(value) => (1, value),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{dce::dce, lower_source, sccp::sccp, ssa::into_ssa, verify::Verifier};
+
+    // The module after the passes that run before GVN, GVN itself and DCE
+    fn number(source: &str) -> String {
+        let mut module = lower_source(source);
+        for function in &mut module.functions {
+            into_ssa(function);
+            assert_eq!(sccp(function), Vec::<String>::new());
+            gvn(function);
+            dce(function);
+        }
+        assert_eq!(
+            Verifier::new(&module, true).get_errors(),
+            &Vec::<String>::new()
+        );
+        module.to_string()
+    }
+
+    #[test]
+    fn repeated_expressions() {
+        assert_eq!(
+            number("fn f(a: u8, b: u8) -> u8 { return (a + b) * (b + a) / (a + b); }"),
+            "fn f(%0: u8, %1: u8) -> u8 {\n\
+             bb0:\n    \
+             %2: u8 = add %0, %1\n    \
+             %4: u8 = mul %2, %2\n    \
+             %6: u8 = div %4, %2\n    \
+             ret %6\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn calls_are_not_merged() {
+        assert_eq!(
+            number("fn f(a: i64) -> i64 { return read_int() + read_int() + f(a) + f(a); }"),
+            "fn f(%0: i64) -> i64 {\n\
+             bb0:\n    \
+             %1: i64 = call read_int()\n    \
+             %2: i64 = call read_int()\n    \
+             %3: i64 = add %1, %2\n    \
+             %4: i64 = call f(%0)\n    \
+             %5: i64 = add %3, %4\n    \
+             %6: i64 = call f(%0)\n    \
+             %7: i64 = add %5, %6\n    \
+             ret %7\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn only_dominating_blocks_are_reused() {
+        assert_eq!(
+            number(
+                "fn f(a: i32, b: i32) -> i32 {\n\
+                 let c: i32 = 0;\n\
+                 if a { c = a * b; } else { c = b * a - 1; }\n\
+                 let d: i32 = a * b;\n\
+                 while d { d = d - a * b; c = c + 1; }\n\
+                 return c + d;\n\
+                 }"
+            ),
+            "fn f(%0: i32, %1: i32) -> i32 {\n\
+             bb0:\n    \
+             cond_br %0, bb1, bb2\n\
+             bb1:\n    \
+             %3: i32 = mul %0, %1\n    \
+             br bb3\n\
+             bb2:\n    \
+             %4: i32 = mul %1, %0\n    \
+             %5: i32 = sub %4, 1\n    \
+             br bb3\n\
+             bb3:\n    \
+             %14: i32 = phi [%3, bb1], [%5, bb2]\n    \
+             %6: i32 = mul %0, %1\n    \
+             br bb4\n\
+             bb4:\n    \
+             %15: i32 = phi [%14, bb3], [%10, bb5]\n    \
+             %16: i32 = phi [%6, bb3], [%9, bb5]\n    \
+             cond_br %16, bb5, bb6\n\
+             bb5:\n    \
+             %9: i32 = sub %16, %6\n    \
+             %10: i32 = add %15, 1\n    \
+             br bb4\n\
+             bb6:\n    \
+             %11: i32 = add %15, %16\n    \
+             ret %11\n\
+             }\n"
+        );
+    }
+}
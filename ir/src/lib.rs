@@ -0,0 +1,367 @@
+use std::fmt::Display;
+
+use ast::{Operator, SimpleType, Span};
+
+pub mod cfg;
+pub mod dce;
+pub mod dominators;
+pub mod gvn;
+pub mod licm;
+pub mod loops;
+pub mod lower;
+pub mod pipeline;
+pub mod sccp;
+pub mod ssa;
+pub mod verify;
+
+// Typed three-address code. Every function has an unbounded number of virtual registers, each
+// with one integer type, and a list of basic blocks starting with the entry block. A block is
+// a list of instructions that each compute at most one register and ends in a terminator that
+// says where control goes next. Lowering gives a variable one register that every assignment
+// copies to, ssa::into_ssa turns that into SSA form where each register has one definition.
+//
+// Arithmetic has the semantics of Operator::evaluate at the type of the destination, both
+// operands have that type too. A condition is true when it is not zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Module {
+    pub functions: Vec<Function>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Reg(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub u32);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub parameters: Vec<Reg>,
+    pub return_type: SimpleType,
+    // Type of every register, indexed by its number
+    pub registers: Vec<SimpleType>,
+    // Indexed by BlockId, the entry block is the first
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+// A register or a constant, which takes the type of the place it is used in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operand {
+    Reg(Reg),
+    Const(i128),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    Copy {
+        dest: Reg,
+        value: Operand,
+    },
+    // span is where the operator is in the source, for reporting traps
+    Binary {
+        dest: Reg,
+        op: Operator,
+        left: Operand,
+        right: Operand,
+        span: Span,
+    },
+    // Converts value from type `from` to the type of dest like SimpleType::wrap
+    Cast {
+        dest: Reg,
+        from: SimpleType,
+        value: Operand,
+    },
+    // Calls a function of the module or a builtin, dest is None for void functions
+    Call {
+        dest: Option<Reg>,
+        function: String,
+        arguments: Vec<Operand>,
+    },
+    // Only at the start of a block, takes the value coming from the predecessor that control
+    // came from. There is one entry per edge, in the order of the predecessors
+    Phi {
+        dest: Reg,
+        incoming: Vec<(BlockId, Operand)>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Terminator {
+    Br(BlockId),
+    CondBr {
+        condition: Operand,
+        then_block: BlockId,
+        else_block: BlockId,
+    },
+    Ret(Option<Operand>),
+    // Ends blocks that control never reaches the end of, like the join after an if whose
+    // branches all return
+    Unreachable,
+}
+
+impl Function {
+    pub fn new_register(&mut self, datatype: SimpleType) -> Reg {
+        self.registers.push(datatype);
+        Reg(self.registers.len() as u32 - 1)
+    }
+
+    pub fn register_type(&self, reg: Reg) -> SimpleType {
+        self.registers[reg.0 as usize]
+    }
+
+    pub fn block(&self, id: BlockId) -> &Block {
+        &self.blocks[id.0 as usize]
+    }
+
+    pub fn block_mut(&mut self, id: BlockId) -> &mut Block {
+        &mut self.blocks[id.0 as usize]
+    }
+
+    // An instruction with the type of its destination
+    pub fn display(&self, instruction: &Instruction) -> String {
+        let dest = match instruction.dest() {
+            // The verifier shows broken instructions too
+            Some(dest) => match self.registers.get(dest.0 as usize) {
+                Some(datatype) => format!("{}: {} = ", dest, datatype),
+                None => format!("{}: ? = ", dest),
+            },
+            None => String::new(),
+        };
+        let rest = match instruction {
+            Instruction::Copy { value, .. } => format!("copy {}", value),
+            Instruction::Binary {
+                op, left, right, ..
+            } => format!("{} {}, {}", opcode(*op), left, right),
+            Instruction::Cast { from, value, .. } => format!("cast {} {}", from, value),
+            Instruction::Call {
+                function,
+                arguments,
+                ..
+            } => {
+                let arguments: Vec<String> = arguments
+                    .iter()
+                    .map(|argument| argument.to_string())
+                    .collect();
+                format!("call {}({})", function, arguments.join(", "))
+            }
+            Instruction::Phi { incoming, .. } => {
+                let incoming: Vec<String> = incoming
+                    .iter()
+                    .map(|(block, value)| format!("[{}, {}]", value, block))
+                    .collect();
+                format!("phi {}", incoming.join(", "))
+            }
+        };
+        format!("{}{}", dest, rest)
+    }
+}
+
+impl Instruction {
+    pub fn dest(&self) -> Option<Reg> {
+        match self {
+            Instruction::Copy { dest, .. }
+            | Instruction::Binary { dest, .. }
+            | Instruction::Cast { dest, .. } => Some(*dest),
+            Instruction::Call { dest, .. } => *dest,
+            Instruction::Phi { dest, .. } => Some(*dest),
+        }
+    }
+
+    pub fn dest_mut(&mut self) -> Option<&mut Reg> {
+        match self {
+            Instruction::Copy { dest, .. }
+            | Instruction::Binary { dest, .. }
+            | Instruction::Cast { dest, .. }
+            | Instruction::Phi { dest, .. } => Some(dest),
+            Instruction::Call { dest, .. } => dest.as_mut(),
+        }
+    }
+
+    pub fn operands(&self) -> Vec<Operand> {
+        match self {
+            Instruction::Copy { value, .. } | Instruction::Cast { value, .. } => vec![*value],
+            Instruction::Binary { left, right, .. } => vec![*left, *right],
+            Instruction::Call { arguments, .. } => arguments.clone(),
+            Instruction::Phi { incoming, .. } => incoming.iter().map(|(_, value)| *value).collect(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Instruction::Copy { value, .. } | Instruction::Cast { value, .. } => vec![value],
+            Instruction::Binary { left, right, .. } => vec![left, right],
+            Instruction::Call { arguments, .. } => arguments.iter_mut().collect(),
+            Instruction::Phi { incoming, .. } => {
+                incoming.iter_mut().map(|(_, value)| value).collect()
+            }
+        }
+    }
+
+    pub fn is_phi(&self) -> bool {
+        matches!(self, Instruction::Phi { .. })
+    }
+
+    // Calls may do anything and a division or remainder traps unless its divisor is a constant
+    // that rules it out, everything else only computes its destination
+    pub fn has_side_effects(&self) -> bool {
+        match self {
+            Instruction::Call { .. } => true,
+            Instruction::Binary {
+                op: Operator::Div | Operator::Mod,
+                right,
+                ..
+            } => !matches!(right, Operand::Const(value) if *value != 0 && *value != -1),
+            _ => false,
+        }
+    }
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Br(target) => vec![*target],
+            Terminator::CondBr {
+                then_block,
+                else_block,
+                ..
+            } => vec![*then_block, *else_block],
+            Terminator::Ret(_) | Terminator::Unreachable => Vec::new(),
+        }
+    }
+
+    pub fn operands(&self) -> Vec<Operand> {
+        match self {
+            Terminator::CondBr { condition, .. } => vec![*condition],
+            Terminator::Ret(Some(value)) => vec![*value],
+            Terminator::Br(_) | Terminator::Ret(None) | Terminator::Unreachable => Vec::new(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Terminator::CondBr { condition, .. } => vec![condition],
+            Terminator::Ret(Some(value)) => vec![value],
+            Terminator::Br(_) | Terminator::Ret(None) | Terminator::Unreachable => Vec::new(),
+        }
+    }
+
+    pub fn targets_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Br(target) => vec![target],
+            Terminator::CondBr {
+                then_block,
+                else_block,
+                ..
+            } => vec![then_block, else_block],
+            Terminator::Ret(_) | Terminator::Unreachable => Vec::new(),
+        }
+    }
+}
+
+// Name of the instruction computing op
+pub fn opcode(op: Operator) -> &'static str {
+    match op {
+        Operator::Plus => "add",
+        Operator::Min => "sub",
+        Operator::Mul => "mul",
+        Operator::Div => "div",
+        Operator::Mod => "rem",
+    }
+}
+
+// The module lowered from a source that has to parse and type check without errors
+#[cfg(test)]
+pub(crate) fn lower_source(source: &str) -> Module {
+    let parser = parser::Parser::new(lexer::Lexer::tokenise_source(source));
+    assert_eq!(parser.get_errors(), &Vec::<String>::new());
+    let mut program = parser.get_program().unwrap().clone();
+    let checker = typeck::TypeCheck::new(&program);
+    assert_eq!(checker.get_errors(), &Vec::<String>::new());
+    checker.rewrite_literals(&mut program);
+    lower::lower_program(&program)
+}
+
+impl Display for Reg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+impl Display for BlockId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operand::Reg(reg) => write!(f, "{}", reg),
+            Operand::Const(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl Display for Module {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", function)?;
+        }
+        Ok(())
+    }
+}
+
+// fn name(%0: u8) -> u8 {
+// bb0:
+//     %1: u8 = add %0, 1
+//     ret %1
+// }
+impl Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parameters: Vec<String> = self
+            .parameters
+            .iter()
+            .map(|reg| format!("{}: {}", reg, self.register_type(*reg)))
+            .collect();
+        writeln!(
+            f,
+            "fn {}({}) -> {} {{",
+            self.name,
+            parameters.join(", "),
+            self.return_type
+        )?;
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "{}:", BlockId(i as u32))?;
+            for instruction in &block.instructions {
+                writeln!(f, "    {}", self.display(instruction))?;
+            }
+            writeln!(f, "    {}", block.terminator)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl Display for Terminator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Terminator::Br(target) => write!(f, "br {}", target),
+            Terminator::CondBr {
+                condition,
+                then_block,
+                else_block,
+            } => write!(f, "cond_br {}, {}, {}", condition, then_block, else_block),
+            Terminator::Ret(Some(value)) => write!(f, "ret {}", value),
+            Terminator::Ret(None) => write!(f, "ret"),
+            Terminator::Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{
+    Function, Operand,
+    cfg::Cfg,
+    dominators::Dominators,
+    loops::{insert_preheaders, natural_loops},
+};
+
+// Loop-invariant code motion over a function in SSA form. Every loop gets a preheader, then an
+// instruction in the loop whose operands are all constants or defined outside of it computes
+// the same value on every trip and is moved to the end of the preheader. Inner loops go first,
+// so an instruction moved out of one can move on out of the loop around it.
+//
+// The preheader runs even when the loop body never does, so only instructions without side
+// effects move: calls stay, and so does a division or remainder that could trap, as it might
+// only run behind a condition that rules the trap out.
+pub fn licm(function: &mut Function) {
+    insert_preheaders(function);
+    let cfg = Cfg::new(function);
+    let dominators = Dominators::new(&cfg);
+    // Instructions only move into preheaders, so the loops stay the same
+    for natural_loop in natural_loops(&cfg, &dominators) {
+        let Some(preheader) = cfg
+            .predecessors(natural_loop.header)
+            .iter()
+            .find(|predecessor| !natural_loop.contains(**predecessor))
+            .copied()
+        else {
+            continue;
+        };
+        let mut defined = vec![false; function.registers.len()];
+        for block in &natural_loop.blocks {
+            for instruction in &function.block(*block).instructions {
+                if let Some(dest) = instruction.dest() {
+                    defined[dest.0 as usize] = true;
+                }
+            }
+        }
+
+        // Definitions dominate their uses, so in reverse postorder the instructions an
+        // invariant one uses have already moved
+        let mut hoisted = Vec::new();
+        for block in cfg.reverse_postorder() {
+            if !natural_loop.contains(*block) {
+                continue;
+            }
+            let instructions = std::mem::take(&mut function.block_mut(*block).instructions);
+            for instruction in instructions {
+                let invariant = !instruction.is_phi()
+                    && !instruction.has_side_effects()
+                    && instruction.operands().iter().all(|operand| match operand {
+                        Operand::Reg(reg) => !defined[reg.0 as usize],
+                        Operand::Const(_) => true,
+                    });
+                if invariant {
+                    defined[instruction.dest().unwrap().0 as usize] = false;
+                    hoisted.push(instruction);
+                } else {
+                    function.block_mut(*block).instructions.push(instruction);
+                }
+            }
+        }
+        function
+            .block_mut(preheader)
+            .instructions
+            .append(&mut hoisted);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{dce::dce, gvn::gvn, lower_source, sccp::sccp, ssa::into_ssa, verify::Verifier};
+
+    // The module after the passes of the pipeline up to LICM and DCE
+    fn hoist(source: &str) -> String {
+        let mut module = lower_source(source);
+        for function in &mut module.functions {
+            into_ssa(function);
+            assert_eq!(sccp(function), Vec::<String>::new());
+            gvn(function);
+            licm(function);
+            dce(function);
+        }
+        assert_eq!(
+            Verifier::new(&module, true).get_errors(),
+            &Vec::<String>::new()
+        );
+        module.to_string()
+    }
+
+    #[test]
+    fn invariants_leave_nested_loops() {
+        // a * b is invariant in both loops, a * b + i only in the inner one
+        assert_eq!(
+            hoist(
+                "fn f(a: u32, b: u32) -> u32 {\n\
+                 let i: u32 = 0; let sum: u32 = 0;\n\
+                 while i - 10 {\n\
+                 let j: u32 = 0;\n\
+                 while j - i { sum = sum + (a * b + i); j = j + 1; }\n\
+                 i = i + 1;\n\
+                 }\n\
+                 return sum;\n\
+                 }"
+            ),
+            "fn f(%0: u32, %1: u32) -> u32 {\n\
+             bb0:\n    \
+             %7: u32 = mul %0, %1\n    \
+             br bb1\n\
+             bb1:\n    \
+             %12: u32 = phi [0, bb0], [%11, bb6]\n    \
+             %13: u32 = phi [0, bb0], [%14, bb6]\n    \
+             %4: u32 = sub %12, 10\n    \
+             cond_br %4, bb2, bb3\n\
+             bb2:\n    \
+             %8: u32 = add %7, %12\n    \
+             br bb4\n\
+             bb3:\n    \
+             ret %13\n\
+             bb4:\n    \
+             %14: u32 = phi [%13, bb2], [%9, bb5]\n    \
+             %15: u32 = phi [0, bb2], [%10, bb5]\n    \
+             %6: u32 = sub %15, %12\n    \
+             cond_br %6, bb5, bb6\n\
+             bb5:\n    \
+             %9: u32 = add %14, %8\n    \
+             %10: u32 = add %15, 1\n    \
+             br bb4\n\
+             bb6:\n    \
+             %11: u32 = add %12, 1\n    \
+             br bb1\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn traps_and_calls_stay() {
+        // Only the division by a constant that cannot trap moves
+        assert_eq!(
+            hoist(
+                "fn f(a: i64, b: i64, c: i64) -> i64 {\n\
+                 let sum: i64 = 0;\n\
+                 while b { sum = sum + a / c + a / 3 + a % (0 - 1) + read_int(); b = b - 1; }\n\
+                 return sum;\n\
+                 }"
+            ),
+            "fn f(%0: i64, %1: i64, %2: i64) -> i64 {\n\
+             bb0:\n    \
+             %6: i64 = div %0, 3\n    \
+             br bb1\n\
+             bb1:\n    \
+             %14: i64 = phi [%1, bb0], [%13, bb2]\n    \
+             %15: i64 = phi [0, bb0], [%12, bb2]\n    \
+             cond_br %14, bb2, bb3\n\
+             bb2:\n    \
+             %4: i64 = div %0, %2\n    \
+             %5: i64 = add %15, %4\n    \
+             %7: i64 = add %5, %6\n    \
+             %9: i64 = rem %0, -1\n    \
+             %10: i64 = add %7, %9\n    \
+             %11: i64 = call read_int()\n    \
+             %12: i64 = add %10, %11\n    \
+             %13: i64 = sub %14, 1\n    \
+             br bb1\n\
+             bb3:\n    \
+             ret %15\n\
+             }\n"
+        );
+    }
+}
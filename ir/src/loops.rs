@@ -0,0 +1,258 @@
+use crate::{
+    Block, BlockId, Function, Instruction, Operand, Terminator, cfg::Cfg, dominators::Dominators,
+};
+
+// A natural loop, the blocks that can reach one of the back edges into its header without
+// going through the header. An edge is a back edge when its target dominates its source.
+// Loops with the same header are one loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loop {
+    pub header: BlockId,
+    // In increasing order, starting with the header unless a block before it is in the loop
+    pub blocks: Vec<BlockId>,
+}
+
+impl Loop {
+    pub fn contains(&self, block: BlockId) -> bool {
+        self.blocks.binary_search(&block).is_ok()
+    }
+}
+
+// The loops of a function, inner loops before the loops around them
+pub fn natural_loops(cfg: &Cfg, dominators: &Dominators) -> Vec<Loop> {
+    let mut loops = Vec::new();
+    for header in cfg.reverse_postorder() {
+        let mut inside = vec![false; cfg.block_count()];
+        inside[header.0 as usize] = true;
+        let mut worklist: Vec<BlockId> = cfg
+            .predecessors(*header)
+            .iter()
+            .filter(|predecessor| dominators.dominates(*header, **predecessor))
+            .copied()
+            .collect();
+        if worklist.is_empty() {
+            continue;
+        }
+        while let Some(block) = worklist.pop() {
+            if inside[block.0 as usize] {
+                continue;
+            }
+            inside[block.0 as usize] = true;
+            worklist.extend(cfg.predecessors(block));
+        }
+        let blocks = (0..cfg.block_count())
+            .filter(|i| inside[*i])
+            .map(|i| BlockId(i as u32))
+            .collect();
+        loops.push(Loop {
+            header: *header,
+            blocks,
+        });
+    }
+    // A loop inside another one has fewer blocks
+    loops.sort_by_key(|natural_loop| natural_loop.blocks.len());
+    loops
+}
+
+// Gives every loop a preheader, a block outside the loop that is the only way into its header
+// and jumps straight to it, so code can run once before the loop. A new block is added after
+// the existing ones when the header is entered from several blocks outside the loop or from a
+// block that also goes elsewhere, phis of the header take the values coming from outside in
+// the new block. Returns how many blocks were added.
+pub fn insert_preheaders(function: &mut Function) -> usize {
+    let headers: Vec<BlockId> = {
+        let cfg = Cfg::new(function);
+        let dominators = Dominators::new(&cfg);
+        natural_loops(&cfg, &dominators)
+            .iter()
+            .map(|natural_loop| natural_loop.header)
+            .collect()
+    };
+    let mut added = 0;
+    for header in headers {
+        // Every preheader added changes the blocks around the next header
+        let cfg = Cfg::new(function);
+        let dominators = Dominators::new(&cfg);
+        let outside: Vec<BlockId> = cfg
+            .predecessors(header)
+            .iter()
+            .filter(|predecessor| !dominators.dominates(header, **predecessor))
+            .copied()
+            .collect();
+        if let [predecessor] = outside.as_slice()
+            && cfg.successors(*predecessor).len() == 1
+        {
+            continue;
+        }
+
+        let preheader = BlockId(function.blocks.len() as u32);
+        let mut instructions = Vec::new();
+        let mut phis = Vec::new();
+        for instruction in function.block(header).instructions.clone() {
+            let Instruction::Phi { dest, incoming } = instruction else {
+                continue;
+            };
+            let (entering, mut staying): (Vec<_>, Vec<_>) = incoming
+                .into_iter()
+                .partition(|(from, _)| outside.contains(from));
+            // One value from outside needs no phi of its own
+            let value = match entering.as_slice() {
+                [(_, value)] => *value,
+                _ => {
+                    let reg = function.new_register(function.register_type(dest));
+                    instructions.push(Instruction::Phi {
+                        dest: reg,
+                        incoming: entering,
+                    });
+                    Operand::Reg(reg)
+                }
+            };
+            staying.push((preheader, value));
+            phis.push(staying);
+        }
+        let mut phis = phis.into_iter();
+        for instruction in &mut function.block_mut(header).instructions {
+            if let Instruction::Phi { incoming, .. } = instruction {
+                *incoming = phis.next().unwrap();
+            }
+        }
+        for predecessor in outside {
+            for target in function.block_mut(predecessor).terminator.targets_mut() {
+                if *target == header {
+                    *target = preheader;
+                }
+            }
+        }
+        function.blocks.push(Block {
+            instructions,
+            terminator: Terminator::Br(header),
+        });
+        added += 1;
+    }
+    added
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Module, Reg, lower_source, ssa::into_ssa, verify::Verifier};
+    use ast::{Operator, SimpleType, Span};
+
+    fn lower(source: &str) -> Function {
+        let mut module = lower_source(source);
+        into_ssa(&mut module.functions[0]);
+        module.functions.remove(0)
+    }
+
+    #[test]
+    fn nested_loops() {
+        let function = lower(
+            "fn f(a: u8) -> u8 {\n\
+             while a { let b: u8 = a; while b { b = b - 1; } a = a - 1; }\n\
+             return a;\n\
+             }",
+        );
+        let cfg = Cfg::new(&function);
+        let loops = natural_loops(&cfg, &Dominators::new(&cfg));
+        let blocks = |ids: &[u32]| ids.iter().map(|id| BlockId(*id)).collect::<Vec<_>>();
+        assert_eq!(
+            loops,
+            vec![
+                Loop {
+                    header: BlockId(4),
+                    blocks: blocks(&[4, 5]),
+                },
+                Loop {
+                    header: BlockId(1),
+                    blocks: blocks(&[1, 2, 4, 5, 6]),
+                },
+            ]
+        );
+        assert!(loops[1].contains(BlockId(5)));
+        assert!(!loops[0].contains(BlockId(6)));
+    }
+
+    #[test]
+    fn preheaders_take_the_values_from_outside() {
+        // Lowering always enters a loop from a block of its own, this one is entered from bb0
+        // and bb1
+        let (a, i, next) = (Reg(0), Reg(1), Reg(2));
+        let block = |instructions, terminator| Block {
+            instructions,
+            terminator,
+        };
+        let mut function = Function {
+            name: String::from("f"),
+            parameters: vec![a],
+            return_type: SimpleType::U8,
+            registers: vec![SimpleType::U8; 3],
+            blocks: vec![
+                block(
+                    Vec::new(),
+                    Terminator::CondBr {
+                        condition: Operand::Reg(a),
+                        then_block: BlockId(1),
+                        else_block: BlockId(2),
+                    },
+                ),
+                block(Vec::new(), Terminator::Br(BlockId(2))),
+                block(
+                    vec![Instruction::Phi {
+                        dest: i,
+                        incoming: vec![
+                            (BlockId(0), Operand::Reg(a)),
+                            (BlockId(1), Operand::Const(1)),
+                            (BlockId(3), Operand::Reg(next)),
+                        ],
+                    }],
+                    Terminator::CondBr {
+                        condition: Operand::Reg(i),
+                        then_block: BlockId(3),
+                        else_block: BlockId(4),
+                    },
+                ),
+                block(
+                    vec![Instruction::Binary {
+                        dest: next,
+                        op: Operator::Min,
+                        left: Operand::Reg(i),
+                        right: Operand::Const(1),
+                        span: Span::default(),
+                    }],
+                    Terminator::Br(BlockId(2)),
+                ),
+                block(Vec::new(), Terminator::Ret(Some(Operand::Reg(i)))),
+            ],
+        };
+
+        assert_eq!(insert_preheaders(&mut function), 1);
+        assert_eq!(insert_preheaders(&mut function), 0);
+        let module = Module {
+            functions: vec![function],
+        };
+        assert_eq!(
+            Verifier::new(&module, true).get_errors(),
+            &Vec::<String>::new()
+        );
+        assert_eq!(
+            module.to_string(),
+            "fn f(%0: u8) -> u8 {\n\
+             bb0:\n    \
+             cond_br %0, bb1, bb5\n\
+             bb1:\n    \
+             br bb5\n\
+             bb2:\n    \
+             %1: u8 = phi [%2, bb3], [%3, bb5]\n    \
+             cond_br %1, bb3, bb4\n\
+             bb3:\n    \
+             %2: u8 = sub %1, 1\n    \
+             br bb2\n\
+             bb4:\n    \
+             ret %1\n\
+             bb5:\n    \
+             %3: u8 = phi [%0, bb0], [1, bb1]\n    \
+             br bb2\n\
+             }\n"
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+
+use ast::*;
+use resolve::builtins::Builtin;
+
+use crate::{Block, BlockId, Function, Instruction, Module, Operand, Reg, Terminator};
+
+// Lowers a program that passed every check and had its literals rewritten. Every parameter,
+// let and expression result gets its own register. An if chain tests its conditions one block
+// after the other and each body jumps to a shared join block, a while loop tests its condition
+// in a header block that the end of the body jumps back to. Statements after one that always
+// returns are never reached and are left out.
+pub fn lower_program(program: &Program) -> Module {
+    let mut return_types: HashMap<&str, SimpleType> = HashMap::new();
+    for declaration in &program.declarations {
+        let Declaration::FunctionDeclaration(function) = declaration;
+        let Type::SimpleType(return_type) = function.return_type;
+        return_types.insert(&function.name, return_type);
+    }
+    let functions = program
+        .declarations
+        .iter()
+        .map(|declaration| {
+            let Declaration::FunctionDeclaration(function) = declaration;
+            lower_function(function, &return_types)
+        })
+        .collect();
+    Module { functions }
+}
+
+fn lower_function(
+    function: &FunctionDeclaration,
+    return_types: &HashMap<&str, SimpleType>,
+) -> Function {
+    let Type::SimpleType(return_type) = function.return_type;
+    let mut lowering = Lowering {
+        return_types,
+        function: Function {
+            name: function.name.clone(),
+            parameters: Vec::new(),
+            return_type,
+            registers: Vec::new(),
+            blocks: Vec::new(),
+        },
+        current: None,
+        scopes: vec![HashMap::new()],
+    };
+    let entry = lowering.new_block();
+    lowering.current = Some(entry);
+    for parameter in &function.parameters {
+        let Type::SimpleType(datatype) = parameter.datatype;
+        let reg = lowering.function.new_register(datatype);
+        lowering.function.parameters.push(reg);
+        lowering.scopes[0].insert(parameter.name.clone(), reg);
+    }
+    lowering.body(&function.body);
+    // The return check makes sure only void functions can reach their end
+    if return_type == SimpleType::Void {
+        lowering.terminate(Terminator::Ret(None));
+    }
+    lowering.function
+}
+
+struct Lowering<'a> {
+    return_types: &'a HashMap<&'a str, SimpleType>,
+    function: Function,
+    // Block that instructions are added to, None once it has been terminated and until the
+    // next reachable block starts
+    current: Option<BlockId>,
+    // Registers of the variables in scope, innermost block last
+    scopes: Vec<HashMap<String, Reg>>,
+}
+
+impl Lowering<'_> {
+    // Blocks start out unreachable until they are terminated
+    fn new_block(&mut self) -> BlockId {
+        self.function.blocks.push(Block {
+            instructions: Vec::new(),
+            terminator: Terminator::Unreachable,
+        });
+        BlockId(self.function.blocks.len() as u32 - 1)
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        let current = self
+            .current
+            .expect("instructions are only emitted when reachable");
+        self.function
+            .block_mut(current)
+            .instructions
+            .push(instruction);
+    }
+
+    fn terminate(&mut self, terminator: Terminator) {
+        if let Some(current) = self.current.take() {
+            self.function.block_mut(current).terminator = terminator;
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Reg {
+        *self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .expect("variables are resolved before lowering")
+    }
+
+    fn body(&mut self, body: &[Statement]) {
+        self.scopes.push(HashMap::new());
+        for statement in body {
+            if self.current.is_none() {
+                break;
+            }
+            self.statement(statement);
+        }
+        self.scopes.pop();
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::DeclarationStatement(declaration) => {
+                let Type::SimpleType(datatype) = declaration.datatype;
+                let (value, _) = self.expression(&declaration.value);
+                let reg = self.function.new_register(datatype);
+                self.emit(Instruction::Copy { dest: reg, value });
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .insert(declaration.variable.clone(), reg);
+            }
+            Statement::VariableAssignmentStatement(assignment) => {
+                let (value, _) = self.expression(&assignment.value);
+                let dest = self.lookup(&assignment.variable);
+                self.emit(Instruction::Copy { dest, value });
+            }
+            Statement::IfStatement(if_statement) => self.if_chain(if_statement),
+            Statement::WhileStatement(while_statement) => {
+                let header = self.new_block();
+                self.terminate(Terminator::Br(header));
+                self.current = Some(header);
+                let (condition, _) = self.expression(&while_statement.condition);
+                let body = self.new_block();
+                let exit = self.new_block();
+                self.terminate(Terminator::CondBr {
+                    condition,
+                    then_block: body,
+                    else_block: exit,
+                });
+                self.current = Some(body);
+                self.body(&while_statement.body);
+                self.terminate(Terminator::Br(header));
+                self.current = Some(exit);
+            }
+            Statement::ReturnStatement(value, _) => {
+                let value = value.as_ref().map(|value| self.expression(value).0);
+                self.terminate(Terminator::Ret(value));
+            }
+            Statement::Expression(Expression::FunctionCallExpression(call)) => {
+                self.call(call);
+            }
+            Statement::Expression(expression) => {
+                self.expression(expression);
+            }
+        }
+    }
+
+    // The join block is only made once a body falls through to it, so an if whose branches all
+    // return leaves the rest of the body unreachable
+    fn if_chain(&mut self, if_statement: &IfStatement) {
+        let mut join = None;
+        let mut next = Some(if_statement);
+        while let Some(if_statement) = next {
+            next = if_statement.tail_conditions.as_deref();
+            let Some(condition) = &if_statement.condition else {
+                self.body(&if_statement.body);
+                self.fall_through(&mut join);
+                break;
+            };
+            let (condition, _) = self.expression(condition);
+            let then_block = self.new_block();
+            let else_block = self.new_block();
+            self.terminate(Terminator::CondBr {
+                condition,
+                then_block,
+                else_block,
+            });
+            self.current = Some(then_block);
+            self.body(&if_statement.body);
+            self.fall_through(&mut join);
+            self.current = Some(else_block);
+            if next.is_none() {
+                self.fall_through(&mut join);
+            }
+        }
+        self.current = join;
+    }
+
+    fn fall_through(&mut self, join: &mut Option<BlockId>) {
+        if self.current.is_some() {
+            let target = *join.get_or_insert_with(|| self.new_block());
+            self.terminate(Terminator::Br(target));
+        }
+    }
+
+    // Result of the expression and its type
+    fn expression(&mut self, expression: &Expression) -> (Operand, SimpleType) {
+        match expression {
+            Expression::LiteralExpression(literal, _) => {
+                let value = literal
+                    .value()
+                    .parse()
+                    .expect("literals are checked before lowering");
+                (Operand::Const(value), literal.datatype())
+            }
+            Expression::VariableReferenceExpression(name, _) => {
+                let reg = self.lookup(name);
+                (Operand::Reg(reg), self.function.register_type(reg))
+            }
+            Expression::BinOpExpression(bin_op) => {
+                let (left, datatype) = self.expression(&bin_op.left);
+                let (right, _) = self.expression(&bin_op.right);
+                let dest = self.function.new_register(datatype);
+                self.emit(Instruction::Binary {
+                    dest,
+                    op: bin_op.op,
+                    left,
+                    right,
+                    span: bin_op.meta.span,
+                });
+                (Operand::Reg(dest), datatype)
+            }
+            Expression::CastExpression(cast) => {
+                let (value, from) = self.expression(&cast.value);
+                let Type::SimpleType(datatype) = cast.datatype;
+                let dest = self.function.new_register(datatype);
+                self.emit(Instruction::Cast { dest, from, value });
+                (Operand::Reg(dest), datatype)
+            }
+            Expression::FunctionCallExpression(call) => {
+                let dest = self
+                    .call(call)
+                    .expect("void calls are only used as statements");
+                (Operand::Reg(dest), self.function.register_type(dest))
+            }
+        }
+    }
+
+    fn call(&mut self, call: &FunctionCallExpression) -> Option<Reg> {
+        let arguments = call
+            .parameters
+            .iter()
+            .map(|argument| self.expression(argument).0)
+            .collect();
+        let return_type = match Builtin::by_name(&call.name) {
+            Some(builtin) => builtin.return_type,
+            None => self.return_types[call.name.as_str()],
+        };
+        let dest =
+            (return_type != SimpleType::Void).then(|| self.function.new_register(return_type));
+        self.emit(Instruction::Call {
+            dest,
+            function: call.name.clone(),
+            arguments,
+        });
+        dest
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{lower_source, verify::Verifier};
+
+    fn lower(source: &str) -> String {
+        let module = lower_source(source);
+        assert_eq!(
+            Verifier::new(&module, false).get_errors(),
+            &Vec::<String>::new()
+        );
+        module.to_string()
+    }
+
+    #[test]
+    fn expressions_become_three_address_code() {
+        assert_eq!(
+            lower(
+                "fn f(a: u8, b: u8) -> u16 {\n\
+                 let c: u8 = a * (b + 1);\n\
+                 a = c;\n\
+                 return c as u16 - f(1, a) / 2;\n\
+                 }\n\
+                 fn main() -> void { print_int(read_int()); f(2, 3); }"
+            ),
+            "fn f(%0: u8, %1: u8) -> u16 {\n\
+             bb0:\n    \
+             %2: u8 = add %1, 1\n    \
+             %3: u8 = mul %0, %2\n    \
+             %4: u8 = copy %3\n    \
+             %0: u8 = copy %4\n    \
+             %5: u16 = cast u8 %4\n    \
+             %6: u16 = call f(1, %0)\n    \
+             %7: u16 = div %6, 2\n    \
+             %8: u16 = sub %5, %7\n    \
+             ret %8\n\
+             }\n\
+             \n\
+             fn main() -> void {\n\
+             bb0:\n    \
+             %0: i64 = call read_int()\n    \
+             call print_int(%0)\n    \
+             %1: u16 = call f(2, 3)\n    \
+             ret\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn if_chains_and_loops() {
+        assert_eq!(
+            lower(
+                "fn f(a: i32) -> i32 {\n\
+                 if a { a = 1; } else if a - 1 { a = 2; } else { a = 3; }\n\
+                 while a { a = a - 1; }\n\
+                 return a;\n\
+                 }"
+            ),
+            "fn f(%0: i32) -> i32 {\n\
+             bb0:\n    \
+             cond_br %0, bb1, bb2\n\
+             bb1:\n    \
+             %0: i32 = copy 1\n    \
+             br bb3\n\
+             bb2:\n    \
+             %1: i32 = sub %0, 1\n    \
+             cond_br %1, bb4, bb5\n\
+             bb3:\n    \
+             br bb6\n\
+             bb4:\n    \
+             %0: i32 = copy 2\n    \
+             br bb3\n\
+             bb5:\n    \
+             %0: i32 = copy 3\n    \
+             br bb3\n\
+             bb6:\n    \
+             cond_br %0, bb7, bb8\n\
+             bb7:\n    \
+             %2: i32 = sub %0, 1\n    \
+             %0: i32 = copy %2\n    \
+             br bb6\n\
+             bb8:\n    \
+             ret %0\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn unreachable_code_is_left_out() {
+        assert_eq!(
+            lower(
+                "fn f(a: u64) -> u64 {\n\
+                 if a { return 1; } else { return 2; }\n\
+                 a = 3;\n\
+                 }\n\
+                 fn g(a: u64) -> void { if a { return; a = 1; } }"
+            ),
+            "fn f(%0: u64) -> u64 {\n\
+             bb0:\n    \
+             cond_br %0, bb1, bb2\n\
+             bb1:\n    \
+             ret 1\n\
+             bb2:\n    \
+             ret 2\n\
+             }\n\
+             \n\
+             fn g(%0: u64) -> void {\n\
+             bb0:\n    \
+             cond_br %0, bb1, bb2\n\
+             bb1:\n    \
+             ret\n\
+             bb2:\n    \
+             br bb3\n\
+             bb3:\n    \
+             ret\n\
+             }\n"
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use ast::Program;
+
+use crate::{
+    Function, Module, dce::dce, gvn::gvn, licm::licm, lower::lower_program, sccp::sccp,
+    ssa::into_ssa, verify::Verifier,
+};
+
+pub enum Failure {
+    // Errors in the program found by the passes
+    Errors(Vec<String>),
+    // A pass left broken IR behind, with the name of the pass and what is wrong
+    InvalidIr(String),
+}
+
+// Lowers a checked program and runs the passes that turn it into the IR the backends get, in
+// SSA form. With verify set the verifier runs after lowering and after every pass, and the
+// first one that leaves broken IR behind is reported with everything that is wrong with it.
+pub fn compile(program: &Program, verify: bool) -> Result<Module, Failure> {
+    let mut module = lower_program(program);
+    let pipeline = Pipeline { verify };
+    pipeline.check("lowering", &module, false)?;
+    pipeline.run(&mut module, "ssa", into_ssa, true)?;
+
+    let mut errors = Vec::new();
+    pipeline.run(
+        &mut module,
+        "sccp",
+        |function| errors.extend(sccp(function)),
+        true,
+    )?;
+    if !errors.is_empty() {
+        return Err(Failure::Errors(errors));
+    }
+    pipeline.run(&mut module, "gvn", gvn, true)?;
+    pipeline.run(&mut module, "licm", licm, true)?;
+    pipeline.run(&mut module, "dce", dce, true)?;
+    Ok(module)
+}
+
+struct Pipeline {
+    verify: bool,
+}
+
+impl Pipeline {
+    // Runs a pass over every function, ssa says whether the pass leaves them in SSA form
+    fn run(
+        &self,
+        module: &mut Module,
+        name: &str,
+        mut pass: impl FnMut(&mut Function),
+        ssa: bool,
+    ) -> Result<(), Failure> {
+        for function in &mut module.functions {
+            pass(function);
+        }
+        self.check(name, module, ssa)
+    }
+
+    fn check(&self, name: &str, module: &Module, ssa: bool) -> Result<(), Failure> {
+        if !self.verify {
+            return Ok(());
+        }
+        let verifier = Verifier::new(module, ssa);
+        match verifier.get_errors().as_slice() {
+            [] => Ok(()),
+            errors => Err(Failure::InvalidIr(format!(
+                "Invalid IR after {}:\n    {}",
+                name,
+                errors.join("\n    ")
+            ))),
+        }
+    }
+}
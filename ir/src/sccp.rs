@@ -0,0 +1,415 @@
+use std::collections::HashSet;
+
+use ast::{Operator, Trap};
+
+use crate::{
+    BlockId, Function, Instruction, Operand, Reg, Terminator, cfg::remove_unreachable_blocks,
+};
+
+// Sparse conditional constant propagation (Wegman and Zadeck) over a function in SSA form.
+// Blocks are only visited once an edge into them can be taken and a cond_br only follows the
+// edges its condition allows, so constants flow through phis of loops and branches that are
+// never taken do not spoil them. Arithmetic follows Operator::evaluate at the type of the
+// destination and casts SimpleType::wrap, so folding gives the same values as running.
+//
+// Afterwards registers found to be constant are replaced by their value and their definitions
+// removed, a cond_br on a constant becomes a br and blocks that can no longer be reached are
+// removed. A division or remainder that always traps when it runs is returned as an error.
+pub fn sccp(function: &mut Function) -> Vec<String> {
+    let mut analysis = Analysis {
+        function,
+        values: vec![Value::Undefined; function.registers.len()],
+        executable: vec![false; function.blocks.len()],
+        edges: HashSet::new(),
+        uses: vec![Vec::new(); function.registers.len()],
+        flow: vec![(None, BlockId(0))],
+        changed: Vec::new(),
+    };
+    for parameter in &function.parameters {
+        analysis.values[parameter.0 as usize] = Value::Overdefined;
+    }
+    for (i, block) in function.blocks.iter().enumerate() {
+        let block_id = BlockId(i as u32);
+        for (j, instruction) in block.instructions.iter().enumerate() {
+            for operand in instruction.operands() {
+                if let Operand::Reg(reg) = operand {
+                    analysis.uses[reg.0 as usize].push((block_id, Some(j)));
+                }
+            }
+        }
+        for operand in block.terminator.operands() {
+            if let Operand::Reg(reg) = operand {
+                analysis.uses[reg.0 as usize].push((block_id, None));
+            }
+        }
+    }
+    analysis.run();
+
+    let Analysis {
+        values,
+        executable,
+        edges,
+        ..
+    } = analysis;
+    let errors = traps(function, &values, &executable);
+    rewrite(function, &values, &executable, &edges);
+    errors
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Value {
+    // Nothing defining it has run yet
+    Undefined,
+    Constant(i128),
+    // Not known at compile time
+    Overdefined,
+}
+
+impl Value {
+    fn meet(self, other: Value) -> Value {
+        match (self, other) {
+            (Value::Undefined, value) | (value, Value::Undefined) => value,
+            (Value::Constant(a), Value::Constant(b)) if a == b => Value::Constant(a),
+            _ => Value::Overdefined,
+        }
+    }
+}
+
+struct Analysis<'a> {
+    function: &'a Function,
+    values: Vec<Value>,
+    executable: Vec<bool>,
+    // Edges found to be taken, from and to
+    edges: HashSet<(BlockId, BlockId)>,
+    // Every instruction reading each register, None for the terminator
+    uses: Vec<Vec<(BlockId, Option<usize>)>>,
+    // Edges still to follow, None comes from outside into the entry
+    flow: Vec<(Option<BlockId>, BlockId)>,
+    // Registers whose value went down since their uses were visited
+    changed: Vec<Reg>,
+}
+
+impl Analysis<'_> {
+    fn run(&mut self) {
+        loop {
+            if let Some((from, to)) = self.flow.pop() {
+                if let Some(from) = from
+                    && !self.edges.insert((from, to))
+                {
+                    continue;
+                }
+                // A new edge only changes the phis of a block that was visited before
+                let first = !self.executable[to.0 as usize];
+                self.executable[to.0 as usize] = true;
+                let block = self.function.block(to);
+                for (i, instruction) in block.instructions.iter().enumerate() {
+                    if first || instruction.is_phi() {
+                        self.instruction(to, i);
+                    }
+                }
+                if first {
+                    self.terminator(to);
+                }
+            } else if let Some(reg) = self.changed.pop() {
+                for (block, index) in self.uses[reg.0 as usize].clone() {
+                    if !self.executable[block.0 as usize] {
+                        continue;
+                    }
+                    match index {
+                        Some(index) => self.instruction(block, index),
+                        None => self.terminator(block),
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn operand(&self, operand: Operand) -> Value {
+        match operand {
+            Operand::Reg(reg) => self.values[reg.0 as usize],
+            Operand::Const(value) => Value::Constant(value),
+        }
+    }
+
+    fn instruction(&mut self, block: BlockId, index: usize) {
+        let function = self.function;
+        let instruction = &function.block(block).instructions[index];
+        let value = match instruction {
+            Instruction::Copy { value, .. } => self.operand(*value),
+            Instruction::Binary {
+                dest,
+                op,
+                left,
+                right,
+                ..
+            } => match (self.operand(*left), self.operand(*right)) {
+                (Value::Constant(left), Value::Constant(right)) => {
+                    match op.evaluate(function.register_type(*dest), left, right) {
+                        Ok(value) => Value::Constant(value),
+                        Err(_) => Value::Overdefined,
+                    }
+                }
+                (Value::Undefined, _) | (_, Value::Undefined) => Value::Undefined,
+                _ => Value::Overdefined,
+            },
+            Instruction::Cast { dest, value, .. } => match self.operand(*value) {
+                Value::Constant(value) => {
+                    Value::Constant(function.register_type(*dest).wrap(value))
+                }
+                value => value,
+            },
+            Instruction::Call { .. } => Value::Overdefined,
+            Instruction::Phi { incoming, .. } => incoming
+                .iter()
+                .filter(|(from, _)| self.edges.contains(&(*from, block)))
+                .fold(Value::Undefined, |value, (_, operand)| {
+                    value.meet(self.operand(*operand))
+                }),
+        };
+        if let Some(dest) = instruction.dest() {
+            let old = self.values[dest.0 as usize];
+            let new = old.meet(value);
+            if new != old {
+                self.values[dest.0 as usize] = new;
+                self.changed.push(dest);
+            }
+        }
+    }
+
+    fn terminator(&mut self, block: BlockId) {
+        match &self.function.block(block).terminator {
+            Terminator::Br(target) => self.flow.push((Some(block), *target)),
+            Terminator::CondBr {
+                condition,
+                then_block,
+                else_block,
+            } => match self.operand(*condition) {
+                Value::Constant(0) => self.flow.push((Some(block), *else_block)),
+                Value::Constant(_) => self.flow.push((Some(block), *then_block)),
+                Value::Overdefined => {
+                    self.flow.push((Some(block), *then_block));
+                    self.flow.push((Some(block), *else_block));
+                }
+                Value::Undefined => {}
+            },
+            Terminator::Ret(_) | Terminator::Unreachable => {}
+        }
+    }
+}
+
+// Divisions in blocks that run by a constant zero, or of constants that overflow
+fn traps(function: &Function, values: &[Value], executable: &[bool]) -> Vec<String> {
+    let value = |operand: Operand| match operand {
+        Operand::Reg(reg) => values[reg.0 as usize],
+        Operand::Const(value) => Value::Constant(value),
+    };
+    let mut errors = Vec::new();
+    for (block, executable) in function.blocks.iter().zip(executable) {
+        if !executable {
+            continue;
+        }
+        for instruction in &block.instructions {
+            let Instruction::Binary {
+                dest,
+                op: op @ (Operator::Div | Operator::Mod),
+                left,
+                right,
+                span,
+            } = instruction
+            else {
+                continue;
+            };
+            let datatype = function.register_type(*dest);
+            match (value(*left), value(*right)) {
+                (_, Value::Constant(0)) => errors.push(format!("Division by zero at {}", span)),
+                (Value::Constant(left), Value::Constant(right))
+                    if op.evaluate(datatype, left, right) == Err(Trap::Overflow) =>
+                {
+                    errors.push(format!(
+                        "Overflow dividing {} by {} as {} at {}",
+                        left, right, datatype, span
+                    ))
+                }
+                _ => {}
+            }
+        }
+    }
+    errors
+}
+
+fn rewrite(
+    function: &mut Function,
+    values: &[Value],
+    executable: &[bool],
+    edges: &HashSet<(BlockId, BlockId)>,
+) {
+    let constant = |reg: Reg| match values[reg.0 as usize] {
+        Value::Constant(value) => Some(value),
+        _ => None,
+    };
+    for (i, runs) in executable.iter().enumerate() {
+        if !runs {
+            continue;
+        }
+        let id = BlockId(i as u32);
+        let block = &mut function.blocks[i];
+        // Calls are never constant, so only instructions without side effects go
+        block
+            .instructions
+            .retain(|instruction| instruction.dest().and_then(constant).is_none());
+        for instruction in &mut block.instructions {
+            if let Instruction::Phi { incoming, .. } = instruction {
+                incoming.retain(|(from, _)| edges.contains(&(*from, id)));
+            }
+            for operand in instruction.operands_mut() {
+                if let Operand::Reg(reg) = operand
+                    && let Some(value) = constant(*reg)
+                {
+                    *operand = Operand::Const(value);
+                }
+            }
+        }
+        for operand in block.terminator.operands_mut() {
+            if let Operand::Reg(reg) = operand
+                && let Some(value) = constant(*reg)
+            {
+                *operand = Operand::Const(value);
+            }
+        }
+
+        let Terminator::CondBr {
+            condition,
+            then_block,
+            else_block,
+        } = block.terminator
+        else {
+            continue;
+        };
+        let (taken, dropped) = match condition {
+            Operand::Const(0) => (else_block, then_block),
+            Operand::Const(_) => (then_block, else_block),
+            // Only a condition computed by code that never runs stays undefined
+            Operand::Reg(reg) if values[reg.0 as usize] == Value::Undefined => {
+                block.terminator = Terminator::Unreachable;
+                continue;
+            }
+            Operand::Reg(_) => continue,
+        };
+        block.terminator = Terminator::Br(taken);
+        // The phis of a target of both edges lose one of the two entries
+        if taken == dropped {
+            for instruction in &mut function.block_mut(taken).instructions {
+                if let Instruction::Phi { incoming, .. } = instruction
+                    && let Some(entry) = incoming.iter().position(|(from, _)| *from == id)
+                {
+                    incoming.remove(entry);
+                }
+            }
+        }
+    }
+    // Blocks that did not run can only be reached from other blocks that did not run now
+    remove_unreachable_blocks(function);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lower_source, ssa::into_ssa, verify::Verifier};
+
+    // The function after SCCP and the errors it found
+    fn sccp_source(source: &str) -> (String, Vec<String>) {
+        let mut module = lower_source(source);
+        let mut errors = Vec::new();
+        for function in &mut module.functions {
+            into_ssa(function);
+            errors.extend(sccp(function));
+        }
+        assert_eq!(
+            Verifier::new(&module, true).get_errors(),
+            &Vec::<String>::new()
+        );
+        (module.to_string(), errors)
+    }
+
+    fn fold(source: &str) -> String {
+        let (module, errors) = sccp_source(source);
+        assert_eq!(errors, Vec::<String>::new());
+        module
+    }
+
+    #[test]
+    fn folds_with_wrapping_and_removes_branches() {
+        assert_eq!(
+            fold(
+                "fn f(a: u8) -> u8 {\n\
+                 let x: u8 = 200;\n\
+                 let y: u8 = x + 100;\n\
+                 if y - 44 { a = 1; } else { a = a + y * 2 as u8; }\n\
+                 return a;\n\
+                 }"
+            ),
+            "fn f(%0: u8) -> u8 {\n\
+             bb0:\n    \
+             br bb1\n\
+             bb1:\n    \
+             %7: u8 = add %0, 88\n    \
+             %8: u8 = copy %7\n    \
+             br bb2\n\
+             bb2:\n    \
+             %10: u8 = phi [%8, bb1]\n    \
+             ret %10\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn constants_flow_around_loops() {
+        // k stays 5 on every trip, i changes
+        assert_eq!(
+            fold(
+                "fn f() -> i16 {\n\
+                 let i: i16 = 0; let k: i16 = 5;\n\
+                 while i - 3 { k = k * 1; i = i + 1; }\n\
+                 return k + (0 - 1) as u8 as i16;\n\
+                 }"
+            ),
+            "fn f() -> i16 {\n\
+             bb0:\n    \
+             br bb1\n\
+             bb1:\n    \
+             %9: i16 = phi [0, bb0], [%12, bb2]\n    \
+             %2: i16 = sub %9, 3\n    \
+             cond_br %2, bb2, bb3\n\
+             bb2:\n    \
+             %4: i16 = add %9, 1\n    \
+             %12: i16 = copy %4\n    \
+             br bb1\n\
+             bb3:\n    \
+             ret 260\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn divisions_that_always_trap() {
+        let (_, errors) = sccp_source(
+            "fn f(a: i8) -> i8 {\n\
+             let zero: i8 = a - a + 0;\n\
+             let m: i8 = 0 - 127 - 1;\n\
+             zero = 0;\n\
+             if a { return a / zero; }\n\
+             if 0 { return a % zero; }\n\
+             return m % (zero - 1);\n\
+             }",
+        );
+        assert_eq!(
+            errors,
+            vec![
+                String::from("Division by zero at 4:16"),
+                String::from("Overflow dividing -128 by -1 as i8 at 6:9"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,468 @@
+use std::collections::BTreeSet;
+
+use ast::SimpleType;
+
+use crate::{
+    Block, BlockId, Function, Instruction, Operand, Reg,
+    cfg::{Cfg, remove_unreachable_blocks, split_critical_edges},
+    dominators::Dominators,
+};
+
+// Puts a function as it comes out of lowering into pruned SSA form. Unreachable blocks are
+// removed first. A register defined more than once, like a variable that is assigned, gets a
+// phi in the iterated dominance frontier of its definitions wherever it is live, then walking
+// the dominator tree gives every definition a register of its own and points every use at the
+// definition that reaches it. The first definition found keeps the old register, so parameters
+// and registers that were only defined once keep their names.
+pub fn into_ssa(function: &mut Function) {
+    remove_unreachable_blocks(function);
+    let cfg = Cfg::new(function);
+    let dominators = Dominators::new(&cfg);
+    let live_in = live_in(function, &cfg);
+
+    // A block for every definition of a register, parameters are defined in the entry
+    let mut definitions: Vec<Vec<BlockId>> = vec![Vec::new(); function.registers.len()];
+    for parameter in &function.parameters {
+        definitions[parameter.0 as usize].push(BlockId(0));
+    }
+    for (i, block) in function.blocks.iter().enumerate() {
+        for instruction in &block.instructions {
+            if let Some(dest) = instruction.dest() {
+                definitions[dest.0 as usize].push(BlockId(i as u32));
+            }
+        }
+    }
+
+    // Register each phi at the start of a block is for
+    let mut phis: Vec<Vec<Reg>> = vec![Vec::new(); function.blocks.len()];
+    for (i, blocks) in definitions.iter().enumerate() {
+        if blocks.len() < 2 {
+            continue;
+        }
+        let reg = Reg(i as u32);
+        let mut has_phi = vec![false; function.blocks.len()];
+        let mut worklist = blocks.clone();
+        while let Some(block) = worklist.pop() {
+            for frontier in dominators.frontier(block) {
+                let index = frontier.0 as usize;
+                if !has_phi[index] && live_in[index].contains(&reg) {
+                    has_phi[index] = true;
+                    phis[index].push(reg);
+                    worklist.push(*frontier);
+                }
+            }
+        }
+    }
+    for (i, registers) in phis.iter().enumerate() {
+        let block = BlockId(i as u32);
+        let instructions = registers.iter().map(|reg| Instruction::Phi {
+            dest: *reg,
+            incoming: cfg
+                .predecessors(block)
+                .iter()
+                .map(|predecessor| (*predecessor, Operand::Reg(*reg)))
+                .collect(),
+        });
+        function.blocks[i]
+            .instructions
+            .splice(0..0, instructions.collect::<Vec<_>>());
+    }
+
+    let mut renamer = Renamer {
+        cfg: &cfg,
+        dominators: &dominators,
+        phis: &phis,
+        stacks: vec![Vec::new(); function.registers.len()],
+        taken: vec![false; function.registers.len()],
+    };
+    for parameter in &function.parameters {
+        renamer.taken[parameter.0 as usize] = true;
+        renamer.stacks[parameter.0 as usize].push(*parameter);
+    }
+    let Function {
+        blocks, registers, ..
+    } = function;
+    renamer.block(blocks, registers, BlockId(0));
+}
+
+// Registers whose value on entry to each block may still be read
+fn live_in(function: &Function, cfg: &Cfg) -> Vec<BTreeSet<Reg>> {
+    let mut uses = vec![BTreeSet::new(); function.blocks.len()];
+    let mut defs = vec![BTreeSet::new(); function.blocks.len()];
+    for (i, block) in function.blocks.iter().enumerate() {
+        let operands = block
+            .instructions
+            .iter()
+            .map(|instruction| (instruction.operands(), instruction.dest()))
+            .chain([(block.terminator.operands(), None)]);
+        for (operands, dest) in operands {
+            for operand in operands {
+                if let Operand::Reg(reg) = operand
+                    && !defs[i].contains(&reg)
+                {
+                    uses[i].insert(reg);
+                }
+            }
+            if let Some(dest) = dest {
+                defs[i].insert(dest);
+            }
+        }
+    }
+
+    let mut live_in = uses.clone();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in cfg.reverse_postorder().iter().rev() {
+            let i = block.0 as usize;
+            let mut live = uses[i].clone();
+            for successor in cfg.successors(*block) {
+                live.extend(live_in[successor.0 as usize].difference(&defs[i]));
+            }
+            if live != live_in[i] {
+                live_in[i] = live;
+                changed = true;
+            }
+        }
+    }
+    live_in
+}
+
+struct Renamer<'a> {
+    cfg: &'a Cfg,
+    dominators: &'a Dominators,
+    phis: &'a [Vec<Reg>],
+    // Register holding the current value of each old register, innermost definition last
+    stacks: Vec<Vec<Reg>>,
+    // Old registers that were given to a definition already
+    taken: Vec<bool>,
+}
+
+impl Renamer<'_> {
+    fn block(&mut self, blocks: &mut [Block], registers: &mut Vec<SimpleType>, block: BlockId) {
+        let mut defined = Vec::new();
+        let phi_count = self.phis[block.0 as usize].len();
+        let current = &mut blocks[block.0 as usize];
+        for (i, instruction) in current.instructions.iter_mut().enumerate() {
+            // Phi operands belong to the edges and are filled in by the predecessors
+            if i >= phi_count {
+                for operand in instruction.operands_mut() {
+                    self.rename_use(operand);
+                }
+            }
+            if let Some(dest) = instruction.dest_mut() {
+                let old = *dest;
+                *dest = self.define(old, registers);
+                defined.push(old);
+            }
+        }
+        for operand in current.terminator.operands_mut() {
+            self.rename_use(operand);
+        }
+
+        let mut successors = self.cfg.successors(block).to_vec();
+        successors.dedup();
+        for successor in successors {
+            let phis = &self.phis[successor.0 as usize];
+            let instructions = &mut blocks[successor.0 as usize].instructions;
+            for (old, instruction) in phis.iter().zip(instructions) {
+                let Instruction::Phi { incoming, .. } = instruction else {
+                    unreachable!("phis were inserted at the start of the block");
+                };
+                // A variable that is not defined on this path is never read after the phi
+                let value = match self.stacks[old.0 as usize].last() {
+                    Some(reg) => Operand::Reg(*reg),
+                    None => Operand::Const(0),
+                };
+                for (from, operand) in incoming {
+                    if *from == block {
+                        *operand = value;
+                    }
+                }
+            }
+        }
+
+        for child in self.dominators.children(block) {
+            self.block(blocks, registers, *child);
+        }
+        for old in defined {
+            self.stacks[old.0 as usize].pop();
+        }
+    }
+
+    fn define(&mut self, old: Reg, registers: &mut Vec<SimpleType>) -> Reg {
+        let new = if self.taken[old.0 as usize] {
+            registers.push(registers[old.0 as usize]);
+            Reg(registers.len() as u32 - 1)
+        } else {
+            self.taken[old.0 as usize] = true;
+            old
+        };
+        self.stacks[old.0 as usize].push(new);
+        new
+    }
+
+    fn rename_use(&self, operand: &mut Operand) {
+        if let Operand::Reg(reg) = operand {
+            *reg = *self.stacks[reg.0 as usize]
+                .last()
+                .expect("registers are defined before they are used");
+        }
+    }
+}
+
+// Replaces the phis of a function in SSA form with copies on the edges they take their values
+// from, after splitting critical edges so that every edge has a block of its own to put them in.
+// The copies into the phis of one block happen at the same time, so they are put in an order
+// that reads every value before it is overwritten.
+pub fn out_of_ssa(function: &mut Function) {
+    split_critical_edges(function);
+    let cfg = Cfg::new(function);
+    for i in 0..function.blocks.len() {
+        let block = BlockId(i as u32);
+        let count = function.blocks[i]
+            .instructions
+            .iter()
+            .take_while(|instruction| instruction.is_phi())
+            .count();
+        if count == 0 {
+            continue;
+        }
+        let phis: Vec<Instruction> = function.blocks[i].instructions.drain(..count).collect();
+        let mut predecessors = cfg.predecessors(block).to_vec();
+        predecessors.dedup();
+        for predecessor in predecessors {
+            let copies = phis
+                .iter()
+                .map(|phi| {
+                    let Instruction::Phi { dest, incoming } = phi else {
+                        unreachable!("only phis were taken");
+                    };
+                    let (_, value) = incoming
+                        .iter()
+                        .find(|(from, _)| *from == predecessor)
+                        .expect("phis have an entry for every predecessor");
+                    (*dest, *value)
+                })
+                .collect();
+            let copies = sequentialise(copies, function);
+            // With critical edges split, either the predecessor only leads here or this block
+            // is only reached from the predecessor
+            if cfg.successors(predecessor).len() == 1 {
+                function.block_mut(predecessor).instructions.extend(copies);
+            } else {
+                function.blocks[i].instructions.splice(0..0, copies);
+            }
+        }
+    }
+}
+
+// Orders parallel copies into destinations that are all different. A copy can go once no other
+// copy still has to read its destination. When none can, the rest form cycles, which are broken
+// by saving the old value of one destination in a new register.
+fn sequentialise(copies: Vec<(Reg, Operand)>, function: &mut Function) -> Vec<Instruction> {
+    let mut pending: Vec<(Reg, Operand)> = copies
+        .into_iter()
+        .filter(|(dest, value)| *value != Operand::Reg(*dest))
+        .collect();
+    let mut sequence = Vec::new();
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|(dest, _)| {
+            !pending
+                .iter()
+                .any(|(_, value)| *value == Operand::Reg(*dest))
+        });
+        match ready {
+            Some(i) => {
+                let (dest, value) = pending.remove(i);
+                sequence.push(Instruction::Copy { dest, value });
+            }
+            None => {
+                let (dest, _) = pending[0];
+                let saved = function.new_register(function.register_type(dest));
+                sequence.push(Instruction::Copy {
+                    dest: saved,
+                    value: Operand::Reg(dest),
+                });
+                for (_, value) in &mut pending {
+                    if *value == Operand::Reg(dest) {
+                        *value = Operand::Reg(saved);
+                    }
+                }
+            }
+        }
+    }
+    sequence
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Module, lower_source, verify::Verifier};
+
+    fn verify(function: &Function, ssa: bool) {
+        let module = Module {
+            functions: vec![function.clone()],
+        };
+        assert_eq!(
+            Verifier::new(&module, ssa).get_errors(),
+            &Vec::<String>::new()
+        );
+    }
+
+    const FIB: &str = "fn fib(n: u64) -> u64 {\n\
+                       let a: u64 = 0; let b: u64 = 1;\n\
+                       while n { let c: u64 = a + b; a = b; b = c; n = n - 1; }\n\
+                       if a { a = a * 2; }\n\
+                       return a;\n\
+                       }";
+
+    #[test]
+    fn phis_at_loop_headers_and_joins() {
+        let mut function = lower_source(FIB).functions.remove(0);
+        into_ssa(&mut function);
+        verify(&function, true);
+        // %7, %8 and %9 are n, a and b in the loop, %11 is a after the if
+        assert_eq!(
+            function.to_string(),
+            "fn fib(%0: u64) -> u64 {\n\
+             bb0:\n    \
+             %1: u64 = copy 0\n    \
+             %2: u64 = copy 1\n    \
+             br bb1\n\
+             bb1:\n    \
+             %7: u64 = phi [%0, bb0], [%14, bb2]\n    \
+             %8: u64 = phi [%1, bb0], [%12, bb2]\n    \
+             %9: u64 = phi [%2, bb0], [%13, bb2]\n    \
+             cond_br %7, bb2, bb3\n\
+             bb2:\n    \
+             %3: u64 = add %8, %9\n    \
+             %4: u64 = copy %3\n    \
+             %12: u64 = copy %9\n    \
+             %13: u64 = copy %4\n    \
+             %5: u64 = sub %7, 1\n    \
+             %14: u64 = copy %5\n    \
+             br bb1\n\
+             bb3:\n    \
+             cond_br %8, bb4, bb5\n\
+             bb4:\n    \
+             %6: u64 = mul %8, 2\n    \
+             %10: u64 = copy %6\n    \
+             br bb6\n\
+             bb5:\n    \
+             br bb6\n\
+             bb6:\n    \
+             %11: u64 = phi [%10, bb4], [%8, bb5]\n    \
+             ret %11\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn every_register_is_defined_once() {
+        let mut function = lower_source(FIB).functions.remove(0);
+        into_ssa(&mut function);
+        verify(&function, true);
+        let mut defined = vec![false; function.registers.len()];
+        for parameter in &function.parameters {
+            defined[parameter.0 as usize] = true;
+        }
+        for block in &function.blocks {
+            for instruction in &block.instructions {
+                let dest = instruction.dest().unwrap();
+                assert!(!defined[dest.0 as usize], "{} is defined twice", dest);
+                defined[dest.0 as usize] = true;
+            }
+        }
+    }
+
+    #[test]
+    fn phis_become_copies() {
+        let mut function = lower_source(FIB).functions.remove(0);
+        into_ssa(&mut function);
+        verify(&function, true);
+        out_of_ssa(&mut function);
+        verify(&function, false);
+        assert_eq!(
+            function.to_string(),
+            "fn fib(%0: u64) -> u64 {\n\
+             bb0:\n    \
+             %1: u64 = copy 0\n    \
+             %2: u64 = copy 1\n    \
+             %7: u64 = copy %0\n    \
+             %8: u64 = copy %1\n    \
+             %9: u64 = copy %2\n    \
+             br bb1\n\
+             bb1:\n    \
+             cond_br %7, bb2, bb3\n\
+             bb2:\n    \
+             %3: u64 = add %8, %9\n    \
+             %4: u64 = copy %3\n    \
+             %12: u64 = copy %9\n    \
+             %13: u64 = copy %4\n    \
+             %5: u64 = sub %7, 1\n    \
+             %14: u64 = copy %5\n    \
+             %7: u64 = copy %14\n    \
+             %8: u64 = copy %12\n    \
+             %9: u64 = copy %13\n    \
+             br bb1\n\
+             bb3:\n    \
+             cond_br %8, bb4, bb5\n\
+             bb4:\n    \
+             %6: u64 = mul %8, 2\n    \
+             %10: u64 = copy %6\n    \
+             %11: u64 = copy %10\n    \
+             br bb6\n\
+             bb5:\n    \
+             %11: u64 = copy %8\n    \
+             br bb6\n\
+             bb6:\n    \
+             ret %11\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn cycles_of_copies_are_broken() {
+        let mut function = lower_source("fn f(a: u8, b: u8, c: u8) -> void {}")
+            .functions
+            .remove(0);
+        let reg = |i| Operand::Reg(Reg(i));
+        // a, b, c = b, a, 7 with a swap, then a rotation through three registers
+        let swap = sequentialise(
+            vec![
+                (Reg(0), reg(1)),
+                (Reg(1), reg(0)),
+                (Reg(2), Operand::Const(7)),
+            ],
+            &mut function,
+        );
+        let rotate = sequentialise(
+            vec![(Reg(0), reg(1)), (Reg(1), reg(2)), (Reg(2), reg(0))],
+            &mut function,
+        );
+        let text = |copies: Vec<Instruction>| -> Vec<String> {
+            copies.iter().map(|copy| function.display(copy)).collect()
+        };
+        assert_eq!(
+            text(swap),
+            vec![
+                "%2: u8 = copy 7",
+                "%3: u8 = copy %0",
+                "%0: u8 = copy %1",
+                "%1: u8 = copy %3"
+            ]
+        );
+        assert_eq!(
+            text(rotate),
+            vec![
+                "%4: u8 = copy %0",
+                "%0: u8 = copy %1",
+                "%1: u8 = copy %2",
+                "%2: u8 = copy %4"
+            ]
+        );
+        let unchanged = sequentialise(vec![(Reg(0), reg(0))], &mut function);
+        assert!(unchanged.is_empty());
+    }
+}
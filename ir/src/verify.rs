@@ -0,0 +1,617 @@
+use std::collections::HashMap;
+
+use ast::SimpleType;
+use resolve::builtins::BUILTINS;
+
+use crate::{
+    BlockId, Function, Instruction, Module, Operand, Reg, Terminator, cfg::Cfg,
+    dominators::Dominators,
+};
+
+// Checks that a module is well formed, meant to run after every pass that rewrites it. The
+// type of Block already makes every block end in exactly one terminator, on top of that branch
+// targets have to exist, every operand has to have the type its instruction expects, calls
+// have to match the signature of their callee and every use has to be preceded by a definition
+// on every path through the function. In SSA form every register is defined once, a definition
+// dominates all of its uses and phis are at the start of a block with one entry for every edge
+// into it. Outside of SSA form there are no phis. Uses in unreachable blocks are not checked.
+pub struct Verifier {
+    errors: Vec<String>,
+}
+
+impl Verifier {
+    pub fn new(module: &Module, ssa: bool) -> Self {
+        let mut signatures: HashMap<&str, (Vec<SimpleType>, SimpleType)> = HashMap::new();
+        for builtin in &BUILTINS {
+            let parameters = builtin
+                .parameters
+                .iter()
+                .map(|(_, datatype)| *datatype)
+                .collect();
+            signatures.insert(builtin.name, (parameters, builtin.return_type));
+        }
+        for function in &module.functions {
+            let parameters = function
+                .parameters
+                .iter()
+                .filter(|reg| (reg.0 as usize) < function.registers.len())
+                .map(|reg| function.register_type(*reg))
+                .collect();
+            signatures.insert(&function.name, (parameters, function.return_type));
+        }
+
+        let mut verifier = Verifier { errors: Vec::new() };
+        for function in &module.functions {
+            let mut check = Check {
+                function,
+                signatures: &signatures,
+                errors: &mut verifier.errors,
+            };
+            check.function(ssa);
+        }
+        verifier
+    }
+
+    pub fn get_errors(&self) -> &Vec<String> {
+        &self.errors
+    }
+}
+
+struct Check<'a> {
+    function: &'a Function,
+    signatures: &'a HashMap<&'a str, (Vec<SimpleType>, SimpleType)>,
+    errors: &'a mut Vec<String>,
+}
+
+impl Check<'_> {
+    fn error(&mut self, block: BlockId, message: String) {
+        self.errors.push(format!(
+            "{} of \"{}\": {}",
+            block, self.function.name, message
+        ));
+    }
+
+    fn function(&mut self, ssa: bool) {
+        let function = self.function;
+        if function.blocks.is_empty() {
+            self.errors
+                .push(format!("Function \"{}\" has no blocks", function.name));
+            return;
+        }
+        for (i, datatype) in function.registers.iter().enumerate() {
+            if *datatype == SimpleType::Void {
+                self.errors.push(format!(
+                    "Register %{} of \"{}\" has type void",
+                    i, function.name
+                ));
+            }
+        }
+        for parameter in &function.parameters {
+            if !self.exists(*parameter) {
+                self.error(
+                    BlockId(0),
+                    format!("parameter {} is not a register", parameter),
+                );
+            }
+        }
+
+        let mut targets_exist = true;
+        for (i, block) in function.blocks.iter().enumerate() {
+            let id = BlockId(i as u32);
+            for target in block.terminator.successors() {
+                if target.0 as usize >= function.blocks.len() {
+                    self.error(
+                        id,
+                        format!(
+                            "{} jumps to {}, which does not exist",
+                            block.terminator, target
+                        ),
+                    );
+                    targets_exist = false;
+                }
+            }
+            let mut phis_allowed = ssa;
+            for instruction in &block.instructions {
+                if instruction.is_phi() && !phis_allowed {
+                    let message = if ssa {
+                        "is not at the start of the block"
+                    } else {
+                        "is not allowed outside of SSA form"
+                    };
+                    self.error(
+                        id,
+                        format!("`{}` {}", function.display(instruction), message),
+                    );
+                }
+                phis_allowed &= instruction.is_phi();
+                self.instruction(id, instruction);
+            }
+            self.terminator(id, &block.terminator);
+        }
+
+        // Everything from here on follows the edges
+        if !targets_exist {
+            return;
+        }
+        let cfg = Cfg::new(function);
+        if ssa {
+            self.phi_entries(&cfg);
+            self.dominance(&cfg);
+        } else {
+            self.definite_definitions(&cfg);
+        }
+    }
+
+    fn exists(&self, reg: Reg) -> bool {
+        (reg.0 as usize) < self.function.registers.len()
+    }
+
+    // Checks that operand can be used where a value of datatype is expected
+    fn operand(&mut self, block: BlockId, context: &str, operand: Operand, datatype: SimpleType) {
+        match operand {
+            Operand::Reg(reg) if !self.exists(reg) => self.error(
+                block,
+                format!("`{}` uses {}, which does not exist", context, reg),
+            ),
+            Operand::Reg(reg) if self.function.register_type(reg) != datatype => self.error(
+                block,
+                format!(
+                    "`{}` uses {} of type {} where {} is expected",
+                    context,
+                    reg,
+                    self.function.register_type(reg),
+                    datatype
+                ),
+            ),
+            Operand::Const(value) => match datatype.range() {
+                Some((min, max)) if value < min || value > max => self.error(
+                    block,
+                    format!(
+                        "`{}` uses {}, which does not fit {}",
+                        context, value, datatype
+                    ),
+                ),
+                _ => {}
+            },
+            Operand::Reg(_) => {}
+        }
+    }
+
+    fn instruction(&mut self, block: BlockId, instruction: &Instruction) {
+        let function = self.function;
+        if let Some(dest) = instruction.dest()
+            && !self.exists(dest)
+        {
+            self.error(block, format!("{} is defined but does not exist", dest));
+            return;
+        }
+        let context = function.display(instruction);
+        match instruction {
+            Instruction::Copy { dest, value } => {
+                self.operand(block, &context, *value, function.register_type(*dest))
+            }
+            Instruction::Binary {
+                dest, left, right, ..
+            } => {
+                let datatype = function.register_type(*dest);
+                self.operand(block, &context, *left, datatype);
+                self.operand(block, &context, *right, datatype);
+            }
+            Instruction::Cast { from, value, .. } => self.operand(block, &context, *value, *from),
+            Instruction::Call {
+                dest,
+                function: callee,
+                arguments,
+            } => {
+                let Some((parameters, return_type)) = self.signatures.get(callee.as_str()) else {
+                    self.error(block, format!("`{}` calls an unknown function", context));
+                    return;
+                };
+                if parameters.len() != arguments.len() {
+                    self.error(
+                        block,
+                        format!(
+                            "`{}` passes {} arguments to a function taking {}",
+                            context,
+                            arguments.len(),
+                            parameters.len()
+                        ),
+                    );
+                    return;
+                }
+                for (argument, datatype) in arguments.iter().zip(parameters) {
+                    self.operand(block, &context, *argument, *datatype);
+                }
+                let returned = dest.map(|dest| function.register_type(dest));
+                let expected = (*return_type != SimpleType::Void).then_some(*return_type);
+                if returned != expected {
+                    self.error(
+                        block,
+                        format!("`{}` calls a function returning {}", context, return_type),
+                    );
+                }
+            }
+            Instruction::Phi { dest, incoming } => {
+                for (_, value) in incoming {
+                    self.operand(block, &context, *value, function.register_type(*dest));
+                }
+            }
+        }
+    }
+
+    fn terminator(&mut self, block: BlockId, terminator: &Terminator) {
+        let context = terminator.to_string();
+        let return_type = self.function.return_type;
+        match terminator {
+            Terminator::CondBr {
+                condition: Operand::Reg(reg),
+                ..
+            } if !self.exists(*reg) => self.error(
+                block,
+                format!("`{}` uses {}, which does not exist", context, reg),
+            ),
+            Terminator::Ret(Some(_)) if return_type == SimpleType::Void => self.error(
+                block,
+                format!("`{}` returns a value from a void function", context),
+            ),
+            Terminator::Ret(Some(value)) => self.operand(block, &context, *value, return_type),
+            Terminator::Ret(None) if return_type != SimpleType::Void => self.error(
+                block,
+                format!(
+                    "`{}` returns nothing from a function returning {}",
+                    context, return_type
+                ),
+            ),
+            _ => {}
+        }
+    }
+
+    fn phi_entries(&mut self, cfg: &Cfg) {
+        let function = self.function;
+        for (i, block) in function.blocks.iter().enumerate() {
+            let id = BlockId(i as u32);
+            let mut predecessors = cfg.predecessors(id).to_vec();
+            predecessors.sort();
+            for instruction in &block.instructions {
+                let Instruction::Phi { incoming, .. } = instruction else {
+                    continue;
+                };
+                let mut entries: Vec<BlockId> = incoming.iter().map(|(from, _)| *from).collect();
+                entries.sort();
+                if entries != predecessors {
+                    self.error(
+                        id,
+                        format!(
+                            "`{}` has entries for [{}] but the predecessors are [{}]",
+                            function.display(instruction),
+                            list(&entries),
+                            list(&predecessors)
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    // Every register has one definition that dominates its uses. A phi uses its operand at the
+    // end of the predecessor it comes from
+    fn dominance(&mut self, cfg: &Cfg) {
+        let function = self.function;
+
+        // Parameters are defined before the first instruction of the entry block, the
+        // instruction at index j right after position j
+        let mut definitions = vec![];
+        for parameter in &function.parameters {
+            definitions.push((*parameter, BlockId(0), 0));
+        }
+        for (i, block) in function.blocks.iter().enumerate() {
+            for (j, instruction) in block.instructions.iter().enumerate() {
+                if let Some(dest) = instruction.dest() {
+                    definitions.push((dest, BlockId(i as u32), j + 1));
+                }
+            }
+        }
+        let mut dominance = Dominance {
+            dominators: Dominators::new(cfg),
+            definitions: vec![None; function.registers.len()],
+        };
+        for (reg, block, position) in definitions {
+            if !self.exists(reg) {
+                continue;
+            }
+            let definition = &mut dominance.definitions[reg.0 as usize];
+            if definition.is_some() {
+                self.error(block, format!("{} is defined more than once", reg));
+            } else {
+                *definition = Some((block, position));
+            }
+        }
+
+        for block in cfg.reverse_postorder() {
+            let current = function.block(*block);
+            for (j, instruction) in current.instructions.iter().enumerate() {
+                let context = function.display(instruction);
+                if let Instruction::Phi { incoming, .. } = instruction {
+                    for (from, value) in incoming {
+                        let end = function.block(*from).instructions.len() + 1;
+                        self.dominated(&dominance, *block, &context, *value, (*from, end));
+                    }
+                } else {
+                    for value in instruction.operands() {
+                        self.dominated(&dominance, *block, &context, value, (*block, j));
+                    }
+                }
+            }
+            let context = current.terminator.to_string();
+            let end = current.instructions.len() + 1;
+            for value in current.terminator.operands() {
+                self.dominated(&dominance, *block, &context, value, (*block, end));
+            }
+        }
+    }
+
+    // Checks that value is defined before it is read at a position in a block, a use in
+    // `block` that reads it somewhere else is a phi
+    fn dominated(
+        &mut self,
+        dominance: &Dominance,
+        block: BlockId,
+        context: &str,
+        value: Operand,
+        (at, position): (BlockId, usize),
+    ) {
+        let Operand::Reg(reg) = value else {
+            return;
+        };
+        // Values coming from unreachable predecessors are never used
+        if !self.exists(reg) || !dominance.dominators.is_reachable(at) {
+            return;
+        }
+        match dominance.definitions[reg.0 as usize] {
+            None => self.error(
+                block,
+                format!("`{}` uses {}, which is never defined", context, reg),
+            ),
+            Some((defined, defined_at)) => {
+                let before = if defined == at {
+                    defined_at <= position
+                } else {
+                    dominance.dominators.dominates(defined, at)
+                };
+                if !before && defined == at {
+                    self.error(
+                        block,
+                        format!("`{}` uses {} before its definition", context, reg),
+                    );
+                } else if !before {
+                    self.error(
+                        block,
+                        format!(
+                            "`{}` uses {}, whose definition in {} does not dominate it",
+                            context, reg, defined
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    // Without SSA a register can be defined in several places, it has to be defined on every
+    // path to each of its uses
+    fn definite_definitions(&mut self, cfg: &Cfg) {
+        let function = self.function;
+        let count = function.registers.len();
+        let order = cfg.reverse_postorder();
+        // Registers defined at the end of each block, everything until a block is visited
+        let mut defined_out = vec![vec![true; count]; function.blocks.len()];
+        let entry_defined = {
+            let mut defined = vec![false; count];
+            for parameter in &function.parameters {
+                if self.exists(*parameter) {
+                    defined[parameter.0 as usize] = true;
+                }
+            }
+            defined
+        };
+        let defined_in = |defined_out: &[Vec<bool>], block: BlockId| -> Vec<bool> {
+            if block == BlockId(0) {
+                return entry_defined.clone();
+            }
+            let mut defined = vec![true; count];
+            for predecessor in cfg.predecessors(block) {
+                if cfg.is_reachable(*predecessor) {
+                    for (defined, out) in
+                        defined.iter_mut().zip(&defined_out[predecessor.0 as usize])
+                    {
+                        *defined &= *out;
+                    }
+                }
+            }
+            defined
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in order {
+                let mut defined = defined_in(&defined_out, *block);
+                for instruction in &function.block(*block).instructions {
+                    if let Some(dest) = instruction.dest()
+                        && self.exists(dest)
+                    {
+                        defined[dest.0 as usize] = true;
+                    }
+                }
+                if defined != defined_out[block.0 as usize] {
+                    defined_out[block.0 as usize] = defined;
+                    changed = true;
+                }
+            }
+        }
+
+        for block in order {
+            let mut defined = defined_in(&defined_out, *block);
+            let current = function.block(*block);
+            for instruction in &current.instructions {
+                let context = function.display(instruction);
+                self.defined(*block, &context, &instruction.operands(), &defined);
+                if let Some(dest) = instruction.dest()
+                    && self.exists(dest)
+                {
+                    defined[dest.0 as usize] = true;
+                }
+            }
+            let context = current.terminator.to_string();
+            self.defined(*block, &context, &current.terminator.operands(), &defined);
+        }
+    }
+
+    fn defined(&mut self, block: BlockId, context: &str, operands: &[Operand], defined: &[bool]) {
+        for operand in operands {
+            if let Operand::Reg(reg) = operand
+                && self.exists(*reg)
+                && !defined[reg.0 as usize]
+            {
+                self.error(
+                    block,
+                    format!(
+                        "`{}` uses {}, which is not defined on every path here",
+                        context, reg
+                    ),
+                );
+            }
+        }
+    }
+}
+
+struct Dominance {
+    dominators: Dominators,
+    // Block and position of the definition of every register
+    definitions: Vec<Option<(BlockId, usize)>>,
+}
+
+fn list(blocks: &[BlockId]) -> String {
+    let blocks: Vec<String> = blocks.iter().map(|block| block.to_string()).collect();
+    blocks.join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        lower_source,
+        ssa::{into_ssa, out_of_ssa},
+    };
+
+    fn errors(module: &Module, ssa: bool) -> Vec<String> {
+        Verifier::new(module, ssa).get_errors().clone()
+    }
+
+    // bb0 branches on a to bb1, which assigns b, and bb2, which joins in bb3 returning b
+    const SOURCE: &str = "fn f(a: u8, b: u8) -> u8 {\n\
+                          if a { let c: u8 = b + 1; b = c; }\n\
+                          return b;\n\
+                          }";
+
+    #[test]
+    fn every_form_verifies() {
+        let mut module = lower_source(SOURCE);
+        assert_eq!(errors(&module, false), Vec::<String>::new());
+        into_ssa(&mut module.functions[0]);
+        assert_eq!(errors(&module, true), Vec::<String>::new());
+        out_of_ssa(&mut module.functions[0]);
+        assert_eq!(errors(&module, false), Vec::<String>::new());
+    }
+
+    #[test]
+    fn types_calls_and_targets() {
+        let mut module = lower_source(SOURCE);
+        let function = &mut module.functions[0];
+        function.blocks[1].instructions[0] = Instruction::Binary {
+            dest: Reg(2),
+            op: ast::Operator::Plus,
+            left: Operand::Reg(Reg(1)),
+            right: Operand::Const(256),
+            span: ast::Span::default(),
+        };
+        let blocks = &mut function.blocks;
+        blocks[2].instructions.push(Instruction::Call {
+            dest: None,
+            function: String::from("print_int"),
+            arguments: vec![Operand::Reg(Reg(1))],
+        });
+        blocks[2].instructions.push(Instruction::Call {
+            dest: Some(Reg(0)),
+            function: String::from("f"),
+            arguments: vec![Operand::Reg(Reg(1))],
+        });
+        blocks[2].terminator = Terminator::Br(BlockId(7));
+        blocks[3].terminator = Terminator::Ret(None);
+        assert_eq!(
+            errors(&module, false),
+            vec![
+                String::from(
+                    "bb1 of \"f\": `%2: u8 = add %1, 256` uses 256, which does not fit u8"
+                ),
+                String::from("bb2 of \"f\": br bb7 jumps to bb7, which does not exist"),
+                String::from(
+                    "bb2 of \"f\": `call print_int(%1)` uses %1 of type u8 where i64 is expected"
+                ),
+                String::from(
+                    "bb2 of \"f\": `%0: u8 = call f(%1)` passes 1 arguments to a function taking 2"
+                ),
+                String::from("bb3 of \"f\": `ret` returns nothing from a function returning u8"),
+            ]
+        );
+    }
+
+    #[test]
+    fn definitions_before_uses() {
+        let mut module = lower_source(SOURCE);
+        // c is only defined when a is not zero
+        module.functions[0].blocks[3].terminator = Terminator::Ret(Some(Operand::Reg(Reg(3))));
+        assert_eq!(
+            errors(&module, false),
+            vec![String::from(
+                "bb3 of \"f\": `ret %3` uses %3, which is not defined on every path here"
+            )]
+        );
+
+        let mut module = lower_source(SOURCE);
+        into_ssa(&mut module.functions[0]);
+        assert_eq!(
+            module.functions[0].blocks[3].instructions,
+            vec![Instruction::Phi {
+                dest: Reg(5),
+                incoming: vec![
+                    (BlockId(1), Operand::Reg(Reg(4))),
+                    (BlockId(2), Operand::Reg(Reg(1)))
+                ],
+            }]
+        );
+        let blocks = &mut module.functions[0].blocks;
+        blocks[3].terminator = Terminator::Ret(Some(Operand::Reg(Reg(4))));
+        blocks[2].instructions.push(Instruction::Copy {
+            dest: Reg(4),
+            value: Operand::Const(0),
+        });
+        blocks[1].instructions.swap(0, 1);
+        let Instruction::Phi { incoming, .. } = &mut blocks[3].instructions[0] else {
+            unreachable!();
+        };
+        incoming.pop();
+        assert_eq!(
+            errors(&module, true),
+            vec![
+                String::from(
+                    "bb3 of \"f\": `%5: u8 = phi [%4, bb1]` has entries for [bb1] but the predecessors are [bb1, bb2]"
+                ),
+                String::from("bb2 of \"f\": %4 is defined more than once"),
+                String::from("bb1 of \"f\": `%3: u8 = copy %2` uses %2 before its definition"),
+                String::from(
+                    "bb3 of \"f\": `ret %4` uses %4, whose definition in bb1 does not dominate it"
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,27 @@
+fn clamp(value: i16, low: i16, high: i16) -> i16 {
+    if (value - low) / (high - low + 1) {
+        return high;
+    }
+    if (value - low) % (high - low + 1) {
+        return value;
+    }
+    return low;
+}
+
+fn checksum(a: u8, b: u8, c: u8) -> u8 {
+    let first: u8 = a * 31 + b;
+    let second: u8 = a * 31 + b;
+    if c {
+        first = first + (a * 31 + b) * c;
+    } else {
+        second = second * (b + a * 31);
+    }
+    return first + second + a * 31;
+}
+
+fn main() -> i32 {
+    let value: i16 = read_int() as i16;
+    print_int(clamp(value, 0 - 10, 10) as i64);
+    print_uint(checksum(value as u8, 7, 3) as u64);
+    return 0;
+}
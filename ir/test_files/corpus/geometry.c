@@ -0,0 +1,19 @@
+fn area(width: i64, height: i64) -> i64 {
+    return width * height;
+}
+
+fn box_surface(x: i64, y: i64, z: i64) -> i64 {
+    return 2 * (x * y) + 2 * (y * z) + 2 * (x * z) + 0 * (y * x);
+}
+
+fn distance_squared(x1: i64, y1: i64, x2: i64, y2: i64) -> i64 {
+    return (x2 - x1) * (x2 - x1) + (y2 - y1) * (y2 - y1);
+}
+
+fn main() -> i32 {
+    let x: i64 = read_int();
+    let y: i64 = read_int();
+    print_int(area(x, y) + box_surface(x, y, x + y));
+    print_int(distance_squared(x, y, y, x));
+    return 0;
+}
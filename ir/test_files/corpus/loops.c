@@ -0,0 +1,29 @@
+fn sum_of_squares(n: u32) -> u32 {
+    let i: u32 = 0;
+    let sum: u32 = 0;
+    while n - i {
+        sum = sum + i * i;
+        if (i * i) % 2 {
+            sum = sum + (i * i) / 2;
+        }
+        i = i + 1;
+    }
+    return sum;
+}
+
+fn triangle(n: u32) -> u32 {
+    let total: u32 = 0;
+    let step: u32 = 1;
+    while n {
+        total = total + n * step;
+        n = n - step;
+    }
+    return total;
+}
+
+fn main() -> i32 {
+    let n: u32 = read_int() as u32;
+    print_uint(sum_of_squares(n) as u64);
+    print_uint(triangle(n) as u64);
+    return 0;
+}
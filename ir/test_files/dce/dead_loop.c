@@ -0,0 +1,10 @@
+fn f(n: i64) -> i64 {
+    let sum: i64 = 0;
+    let square: i64 = n * n;
+    let i: i64 = 0;
+    while i - n {
+        sum = sum + i * square;
+        i = i + 1;
+    }
+    return n;
+}
@@ -0,0 +1,10 @@
+fn f(a: u8) -> u8 {
+    let x: u8 = 200;
+    let y: u8 = x + 100;
+    if y - 44 {
+        a = 1;
+    } else {
+        a = a + y * 2 as u8;
+    }
+    return a;
+}
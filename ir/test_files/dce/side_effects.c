@@ -0,0 +1,12 @@
+fn g(a: i32) -> i32 {
+    print_int(a as i64);
+    return a;
+}
+
+fn f(a: i32, b: i32) -> void {
+    let half: i32 = a / 2;
+    let quotient: i32 = a / b;
+    let negated: i32 = a / (0 - 1);
+    let unused: i32 = g(half + 1);
+    read_int();
+}
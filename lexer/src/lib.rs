@@ -4,7 +4,10 @@ use std::{
     io::{BufRead, BufReader},
 };
 
+use intern::Interner;
+
 #[derive(PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TokenType {
     Id(String),
     Num(String),
@@ -46,53 +49,55 @@ pub enum TokenType {
     End,
     Comma,
     Let,
+    As,
     Invalid,
 }
 
 impl Debug for TokenType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let t = match self {
-            TokenType::Semi => format!("SEMI"),
-            TokenType::Rcur => format!("RCUR"),
-            TokenType::Lcur => format!("LCUR"),
-            TokenType::Lang => format!("LANG"),
-            TokenType::Rang => format!("RANG"),
-            TokenType::Lbra => format!("LBRA"),
-            TokenType::Rbra => format!("RBRA"),
-            TokenType::Lt => format!("LT"),
-            TokenType::Lteq => format!("LTEQ"),
-            TokenType::Gt => format!("GT"),
-            TokenType::Gteq => format!("GTEQ"),
-            TokenType::Eq => format!("EQ"),
-            TokenType::Eqeq => format!("EQEQ"),
-            TokenType::Plus => format!("PLUS"),
-            TokenType::Min => format!("MIN"),
-            TokenType::Mul => format!("MUL"),
-            TokenType::Div => format!("DIV"),
-            TokenType::Mod => format!("MOD"),
+            TokenType::Semi => String::from("SEMI"),
+            TokenType::Rcur => String::from("RCUR"),
+            TokenType::Lcur => String::from("LCUR"),
+            TokenType::Lang => String::from("LANG"),
+            TokenType::Rang => String::from("RANG"),
+            TokenType::Lbra => String::from("LBRA"),
+            TokenType::Rbra => String::from("RBRA"),
+            TokenType::Lt => String::from("LT"),
+            TokenType::Lteq => String::from("LTEQ"),
+            TokenType::Gt => String::from("GT"),
+            TokenType::Gteq => String::from("GTEQ"),
+            TokenType::Eq => String::from("EQ"),
+            TokenType::Eqeq => String::from("EQEQ"),
+            TokenType::Plus => String::from("PLUS"),
+            TokenType::Min => String::from("MIN"),
+            TokenType::Mul => String::from("MUL"),
+            TokenType::Div => String::from("DIV"),
+            TokenType::Mod => String::from("MOD"),
             TokenType::Id(id) => format!("ID:{}", id),
             TokenType::Num(num) => format!("NUM:{}", num),
-            TokenType::If => format!("IF"),
-            TokenType::Else => format!("ELSE"),
-            TokenType::While => format!("WHILE"),
-            TokenType::For => format!("FOR"),
-            TokenType::Return => format!("RET"),
-            TokenType::Void => format!("VOID"),
-            TokenType::Invalid => format!("INVALID"),
-            TokenType::Fn => format!("FN"),
-            TokenType::Colon => format!("COLON"),
-            TokenType::Arrow => format!("ARROW"),
-            TokenType::End => format!("END"),
-            TokenType::Comma => format!("COMMA"),
-            TokenType::Let => format!("LET"),
-            TokenType::I8 => format!("i8"),
-            TokenType::I16 => format!("i16"),
-            TokenType::I32 => format!("i32"),
-            TokenType::I64 => format!("i64"),
-            TokenType::U8 => format!("u8"),
-            TokenType::U16 => format!("u16"),
-            TokenType::U32 => format!("u32"),
-            TokenType::U64 => format!("u64"),
+            TokenType::If => String::from("IF"),
+            TokenType::Else => String::from("ELSE"),
+            TokenType::While => String::from("WHILE"),
+            TokenType::For => String::from("FOR"),
+            TokenType::Return => String::from("RET"),
+            TokenType::Void => String::from("VOID"),
+            TokenType::Invalid => String::from("INVALID"),
+            TokenType::Fn => String::from("FN"),
+            TokenType::Colon => String::from("COLON"),
+            TokenType::Arrow => String::from("ARROW"),
+            TokenType::End => String::from("END"),
+            TokenType::Comma => String::from("COMMA"),
+            TokenType::Let => String::from("LET"),
+            TokenType::As => String::from("AS"),
+            TokenType::I8 => String::from("i8"),
+            TokenType::I16 => String::from("i16"),
+            TokenType::I32 => String::from("i32"),
+            TokenType::I64 => String::from("i64"),
+            TokenType::U8 => String::from("u8"),
+            TokenType::U16 => String::from("u16"),
+            TokenType::U32 => String::from("u32"),
+            TokenType::U64 => String::from("u64"),
         };
         write!(f, "{}", t)
     }
@@ -141,6 +146,7 @@ impl TokenType {
             TokenType::End => String::from("EOF"),
             TokenType::Comma => String::from("comma"),
             TokenType::Let => String::from("let"),
+            TokenType::As => String::from("as"),
             TokenType::Invalid => String::from("INVALID"),
         }
     }
@@ -157,6 +163,7 @@ impl Debug for Token {
 }
 
 #[derive(PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Token {
     token_type: TokenType,
     line_nr: usize,
@@ -164,7 +171,7 @@ pub struct Token {
 }
 
 impl Token {
-    fn new(token_type: TokenType, line_nr: usize, line_index: usize) -> Self {
+    pub fn new(token_type: TokenType, line_nr: usize, line_index: usize) -> Self {
         Self {
             token_type,
             line_nr,
@@ -188,6 +195,8 @@ impl Token {
 pub struct Lexer {
     path: String,
     tokens: Vec<Token>,
+    // Every identifier seen so far, later stages keep interning into the same table
+    interner: Interner,
 }
 
 impl Debug for Lexer {
@@ -195,7 +204,7 @@ impl Debug for Lexer {
         for token in &self.tokens {
             write!(f, "{:?}", token).unwrap();
         }
-        write!(f, "\n")
+        writeln!(f)
     }
 }
 
@@ -204,6 +213,7 @@ impl Lexer {
         Self {
             path: path.to_string(),
             tokens: Vec::new(),
+            interner: Interner::new(),
         }
     }
 
@@ -214,6 +224,9 @@ impl Lexer {
         for (nr, line) in lines.map_while(Result::ok).enumerate() {
             let tokens = Lexer::tokenise_line(&line, nr);
             for token in tokens {
+                if let TokenType::Id(id) = token.get_type() {
+                    self.interner.intern(id);
+                }
                 self.tokens.push(token);
             }
         }
@@ -224,6 +237,20 @@ impl Lexer {
         self.tokens.clone()
     }
 
+    pub fn take_interner(&mut self) -> Interner {
+        std::mem::take(&mut self.interner)
+    }
+
+    // Tokenises source text that does not come from a file, e.g. in tests
+    pub fn tokenise_source(source: &str) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        for (nr, line) in source.lines().enumerate() {
+            tokens.append(&mut Lexer::tokenise_line(line, nr));
+        }
+        tokens.push(Token::new(TokenType::End, 0, 0));
+        tokens
+    }
+
     fn tokenise_line(line: &str, line_nr: usize) -> Vec<Token> {
         let mut tokens = Vec::new();
         let mut i = 0;
@@ -278,18 +305,12 @@ impl Lexer {
                 let c = line.chars().nth(i + 1);
 
                 match c {
-                    Some(c) => {
-                        if c == '>' {
-                            token_type = TokenType::Arrow;
-                            index = i;
-                            i += 2;
-                        } else {
-                            token_type = TokenType::Min;
-                            index = i;
-                            i += 1;
-                        }
+                    Some('>') => {
+                        token_type = TokenType::Arrow;
+                        index = i;
+                        i += 2;
                     }
-                    None => {
+                    _ => {
                         token_type = TokenType::Min;
                         index = i;
                         i += 1;
@@ -325,64 +346,46 @@ impl Lexer {
                 let c = line.chars().nth(i + 1);
 
                 match c {
-                    None => {
+                    Some('=') => {
+                        token_type = TokenType::Eqeq;
+                        index = i;
+                        i += 2;
+                    }
+                    _ => {
                         token_type = TokenType::Eq;
                         index = i;
                         i += 1;
                     }
-                    Some(c) => {
-                        if c == '=' {
-                            token_type = TokenType::Eqeq;
-                            index = i;
-                            i += 2;
-                        } else {
-                            token_type = TokenType::Eq;
-                            index = i;
-                            i += 1;
-                        }
-                    }
                 }
             } else if c == '<' {
                 let c = line.chars().nth(i + 1);
 
                 match c {
-                    None => {
+                    Some('=') => {
+                        token_type = TokenType::Lteq;
+                        index = i;
+                        i += 2;
+                    }
+                    _ => {
                         token_type = TokenType::Lt;
                         index = i;
                         i += 1;
                     }
-                    Some(c) => {
-                        if c == '=' {
-                            token_type = TokenType::Lteq;
-                            index = i;
-                            i += 2;
-                        } else {
-                            token_type = TokenType::Lt;
-                            index = i;
-                            i += 1;
-                        }
-                    }
                 }
             } else if c == '>' {
                 let c = line.chars().nth(i + 1);
 
                 match c {
-                    None => {
+                    Some('=') => {
+                        token_type = TokenType::Gteq;
+                        index = i;
+                        i += 2;
+                    }
+                    _ => {
                         token_type = TokenType::Gt;
                         index = i;
                         i += 1;
                     }
-                    Some(c) => {
-                        if c == '=' {
-                            token_type = TokenType::Gteq;
-                            index = i;
-                            i += 2;
-                        } else {
-                            token_type = TokenType::Gt;
-                            index = i;
-                            i += 1;
-                        }
-                    }
                 }
             } else if c == ':' {
                 token_type = TokenType::Colon;
@@ -444,6 +447,8 @@ impl Lexer {
                     token_type = TokenType::Let;
                 } else if "fn" == &id {
                     token_type = TokenType::Fn;
+                } else if "as" == &id {
+                    token_type = TokenType::As;
                 } else {
                     token_type = TokenType::Id(id);
                 }
@@ -500,13 +505,35 @@ mod test {
             Token::new(TokenType::Num(String::from("2")), 1, 12),
             Token::new(TokenType::Semi, 1, 13),
             Token::new(TokenType::Rcur, 2, 0),
+            Token::new(TokenType::End, 0, 0),
         ];
-        let mut lexer = Lexer::new(&path);
+        let mut lexer = Lexer::new(path);
         lexer.tokenise();
 
         assert_eq!(lexer.tokens.len(), result.len());
-        for i in 0..result.len() {
-            assert_eq!(result[i], lexer.tokens[i]);
+        for (expected, token) in result.iter().zip(&lexer.tokens) {
+            assert_eq!(expected, token);
         }
+
+        let interner = lexer.take_interner();
+        assert_eq!(interner.len(), 2);
+        assert_eq!(interner.resolve(interner.lookup("v").unwrap()), "v");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn tokens_to_json_and_back() {
+        let tokens = Lexer::tokenise_source("fn f(a: u8)\n  return 12;");
+        let json = serde_json::to_value(&tokens).unwrap();
+        assert_eq!(
+            json[1],
+            serde_json::json!({"token_type": {"Id": "f"}, "line_nr": 0, "line_index": 3})
+        );
+        assert_eq!(
+            json[8],
+            serde_json::json!({"token_type": {"Num": "12"}, "line_nr": 1, "line_index": 9})
+        );
+        let back: Vec<Token> = serde_json::from_value(json).unwrap();
+        assert_eq!(back, tokens);
     }
 }
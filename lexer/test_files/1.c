@@ -1,3 +1,3 @@
-int double(int v) {
+i32 double(i32 v) {
 	return v * 2;
 }
@@ -1,3 +1,7 @@
+// Every production is written as a match on the lookahead token, including the ones with a
+// single non-empty alternative, so the code keeps mirroring the grammar
+#![allow(clippy::single_match)]
+
 use std::fmt::Debug;
 
 use ast::*;
@@ -17,7 +21,10 @@ impl Debug for Parser {
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
+    pub fn new(mut tokens: Vec<Token>) -> Self {
+        if tokens.last().map(|t| t.get_type()) != Some(&TokenType::End) {
+            tokens.push(Token::new(TokenType::End, 0, 0));
+        }
         let mut parser = Parser {
             tokens,
             index: 0,
@@ -51,27 +58,49 @@ impl Parser {
             t.get_line_index()
         );
 
-        for i in 0..(expected.len() - 1) {
-            error_msg.push_str(format!("\"{}\", ", expected[i].debug_type()).as_str());
+        for token_type in &expected[..expected.len() - 1] {
+            error_msg.push_str(format!("\"{}\", ", token_type.debug_type()).as_str());
         }
 
-        error_msg
-            .push_str(format!("or \"{}\"", expected[expected.len() - 1].debug_type()).as_str());
+        if expected.len() > 1 {
+            error_msg.push_str("or ");
+        }
+        error_msg.push_str(format!("\"{}\"", expected[expected.len() - 1].debug_type()).as_str());
 
         self.errors.push(error_msg);
     }
 
+    // Placeholder node returned after an error so parsing can continue
+    fn error_expression() -> Expression {
+        Expression::LiteralExpression(LiteralExpression::I64(String::from("0")), Meta::default())
+    }
+
+    // Reading past the end keeps returning the last token, which is END for lexer output
     fn next(&self) -> &Token {
-        &self.tokens[self.index]
+        &self.tokens[self.index.min(self.tokens.len() - 1)]
     }
 
     fn next_next(&self) -> &Token {
-        &self.tokens[self.index + 1]
+        &self.tokens[(self.index + 1).min(self.tokens.len() - 1)]
+    }
+
+    fn span(&self) -> Span {
+        let t = self.next();
+        Span::new(t.get_line_nr(), t.get_line_index())
     }
 
     fn parse(&mut self) {
-        self.program = Some(self.program());
-        println!("Parsing succeeded YAY!");
+        let mut program = self.program();
+        program.assign_node_ids();
+        self.program = Some(program);
+    }
+
+    pub fn get_program(&self) -> Option<&Program> {
+        self.program.as_ref()
+    }
+
+    pub fn get_errors(&self) -> &Vec<String> {
+        &self.errors
     }
 
     // program -> function_list "$"
@@ -108,6 +137,7 @@ impl Parser {
     // function -> "fn" identifier "(" parameter_declaration_list ")" "->" type "{" body "}"
     fn function(&mut self) -> FunctionDeclaration {
         self.eat(TokenType::Fn);
+        let span = self.span();
         let name = self.parse_identifier();
         self.eat(TokenType::Lbra);
         let parameters = self.parameter_declaration_list();
@@ -117,7 +147,10 @@ impl Parser {
         self.eat(TokenType::Lcur);
         let body = self.parse_body();
         self.eat(TokenType::Rcur);
-        FunctionDeclaration::new(name, parameters, return_type, body)
+        FunctionDeclaration {
+            meta: Meta::at(span),
+            ..FunctionDeclaration::new(name, parameters, return_type, body)
+        }
     }
 
     // paramter_declaration_list -> parameter_declaration parameter_declaration_tail | e
@@ -151,10 +184,14 @@ impl Parser {
 
     // parameter_declaration -> identifier ":" type
     fn parameter_declaration(&mut self) -> FunctionParameterDeclaration {
+        let span = self.span();
         let name = self.parse_identifier();
         self.eat(TokenType::Colon);
         let datatype = self.parse_type();
-        FunctionParameterDeclaration::new(name, datatype)
+        FunctionParameterDeclaration {
+            meta: Meta::at(span),
+            ..FunctionParameterDeclaration::new(name, datatype)
+        }
     }
 
     // type,  TODO: add support for custom defined types in the future
@@ -226,16 +263,16 @@ impl Parser {
             TokenType::If | TokenType::While => self.parse_block_statement(),
             _ => {
                 self.error(vec![
-                TokenType::Let,
-                TokenType::Id(String::new()),
-                TokenType::Return,
-                TokenType::Num(String::new()),
-                TokenType::Lbra,
-                TokenType::If,
-                TokenType::While,
-            ]);
-                return Statement::
-            },
+                    TokenType::Let,
+                    TokenType::Id(String::new()),
+                    TokenType::Return,
+                    TokenType::Num(String::new()),
+                    TokenType::Lbra,
+                    TokenType::If,
+                    TokenType::While,
+                ]);
+                Statement::Expression(Parser::error_expression())
+            }
         }
     }
 
@@ -244,7 +281,10 @@ impl Parser {
         match self.next().get_type() {
             TokenType::If => Statement::IfStatement(self.parse_if_statement()),
             TokenType::While => Statement::WhileStatement(self.parse_while_statement()),
-            _ => self.error(vec![TokenType::If, TokenType::While]),
+            _ => {
+                self.error(vec![TokenType::If, TokenType::While]);
+                Statement::Expression(Parser::error_expression())
+            }
         }
     }
 
@@ -260,26 +300,36 @@ impl Parser {
                 }
                 _ => Statement::Expression(self.parse_expression()),
             },
-            TokenType::Return => Statement::ReturnStatement(self.parse_return_statement()),
+            TokenType::Return => {
+                let span = self.span();
+                Statement::ReturnStatement(self.parse_return_statement(), Meta::at(span))
+            }
             TokenType::Num(_) | TokenType::Lbra => Statement::Expression(self.parse_expression()),
-            _ => self.error(vec![
-                TokenType::Let,
-                TokenType::Id(String::new()),
-                TokenType::Return,
-                TokenType::Num(String::new()),
-            ]),
+            _ => {
+                self.error(vec![
+                    TokenType::Let,
+                    TokenType::Id(String::new()),
+                    TokenType::Return,
+                    TokenType::Num(String::new()),
+                ]);
+                Statement::Expression(Parser::error_expression())
+            }
         }
     }
 
     // if_statement -> "if" expression "{" body "}" maybe_else_statement
     fn parse_if_statement(&mut self) -> IfStatement {
+        let span = self.span();
         self.eat(TokenType::If);
         let condition = self.parse_expression();
         self.eat(TokenType::Lcur);
         let body = self.parse_body();
         self.eat(TokenType::Rcur);
         let tail = self.parse_maybe_else_statement();
-        IfStatement::new(condition, body, tail)
+        IfStatement {
+            meta: Meta::at(span),
+            ..IfStatement::new(condition, body, tail)
+        }
     }
 
     // maybe_else_statement -> "else" else_statement | e
@@ -298,49 +348,71 @@ impl Parser {
         match self.next().get_type() {
             TokenType::If => self.parse_if_statement(),
             TokenType::Lcur => {
+                let span = self.span();
                 self.eat(TokenType::Lcur);
                 let body = self.parse_body();
                 self.eat(TokenType::Rcur);
-                IfStatement::make_else(body)
+                IfStatement {
+                    meta: Meta::at(span),
+                    ..IfStatement::make_else(body)
+                }
+            }
+            _ => {
+                self.error(vec![TokenType::If, TokenType::Lcur]);
+                IfStatement::make_else(Vec::new())
             }
-            _ => self.error(vec![TokenType::If, TokenType::Lcur]),
         }
     }
 
     // while_statement -> "while" expression "{" body "}"
     fn parse_while_statement(&mut self) -> WhileStatement {
+        let span = self.span();
         self.eat(TokenType::While);
         let condition = self.parse_expression();
         self.eat(TokenType::Lcur);
         let body = self.parse_body();
         self.eat(TokenType::Rcur);
-        WhileStatement::new(condition, body)
+        WhileStatement {
+            meta: Meta::at(span),
+            ..WhileStatement::new(condition, body)
+        }
     }
 
     // declaration_statement -> "let" identifier ":" type "=" expression
     fn parse_declaration_statement(&mut self) -> DeclarationStatement {
         self.eat(TokenType::Let);
+        let span = self.span();
         let variable = self.parse_identifier();
         self.eat(TokenType::Colon);
         let datatype = self.parse_type();
         self.eat(TokenType::Eq);
         let value = self.parse_expression();
-        DeclarationStatement::new(variable, datatype, value)
+        DeclarationStatement {
+            meta: Meta::at(span),
+            ..DeclarationStatement::new(variable, datatype, value)
+        }
     }
 
     // assignment_statement -> lvalue "=" expression
     fn parse_assignment_statement(&mut self) -> VariableAssignmentStatement {
+        let span = self.span();
         let variable = self.parse_lvalue();
         self.eat(TokenType::Eq);
         let value = self.parse_expression();
-        VariableAssignmentStatement::new(variable, value)
+        VariableAssignmentStatement {
+            meta: Meta::at(span),
+            ..VariableAssignmentStatement::new(variable, value)
+        }
     }
 
     // lvalue -> identifier
     fn parse_lvalue(&mut self) -> String {
         match self.next().get_type() {
             TokenType::Id(_) => self.parse_identifier(),
-            _ => self.error(vec![TokenType::Id(String::new())]),
+            _ => {
+                self.error(vec![TokenType::Id(String::new())]);
+                String::new()
+            }
         }
     }
 
@@ -362,31 +434,54 @@ impl Parser {
     fn parse_expression(&mut self) -> Expression {
         // number, identifier, (
         let expression = self.parse_term();
-        let expression = self.parse_expression_prime(expression);
-        expression
+        self.parse_expression_prime(expression)
     }
 
-    // term -> factor term'
+    // term -> cast term'
     fn parse_term(&mut self) -> Expression {
+        // number, identifier, (
+        let expression = self.parse_cast();
+        self.parse_term_prime(expression)
+    }
+
+    // cast -> factor cast'
+    fn parse_cast(&mut self) -> Expression {
         // number, identifier, (
         let expression = self.parse_factor();
-        let expression = self.parse_term_prime(expression);
-        expression
+        self.parse_cast_prime(expression)
+    }
+
+    // cast' -> "as" type cast' | e
+    fn parse_cast_prime(&mut self, value: Expression) -> Expression {
+        match self.next().get_type() {
+            TokenType::As => {
+                let span = self.span();
+                self.eat(TokenType::As);
+                let datatype = self.parse_type();
+                let cast_expression = CastExpression {
+                    meta: Meta::at(span),
+                    ..CastExpression::new(value, datatype)
+                };
+                self.parse_cast_prime(Expression::CastExpression(cast_expression))
+            }
+            _ => value,
+        }
     }
 
     // factor -> number | identifier | function_call | "(" expression ")"
     fn parse_factor(&mut self) -> Expression {
         // number, identifier, (
+        let span = self.span();
         match self.next().get_type() {
             TokenType::Num(_) => {
                 let value = self.parse_number();
-                Expression::LiteralExpression(LiteralExpression::I64(value))
+                Expression::LiteralExpression(LiteralExpression::I64(value), Meta::at(span))
             }
             TokenType::Id(_) => match self.next_next().get_type() {
                 TokenType::Lbra => Expression::FunctionCallExpression(self.parse_function_call()),
                 _ => {
                     let value = self.parse_identifier();
-                    Expression::VariableReferenceExpression(value)
+                    Expression::VariableReferenceExpression(value, Meta::at(span))
                 }
             },
             TokenType::Lbra => {
@@ -401,13 +496,18 @@ impl Parser {
                     TokenType::Id(String::new()),
                     TokenType::Lbra,
                 ]);
+                Parser::error_expression()
             }
         }
     }
 
     fn parse_function_call(&mut self) -> FunctionCallExpression {
+        let span = self.span();
         let name = self.parse_identifier();
-        let mut function_call_expression = FunctionCallExpression::new(name);
+        let mut function_call_expression = FunctionCallExpression {
+            meta: Meta::at(span),
+            ..FunctionCallExpression::new(name)
+        };
         self.eat(TokenType::Lbra);
         let parameters = self.parse_parameter_list();
         function_call_expression.add_parameters(parameters);
@@ -464,11 +564,14 @@ impl Parser {
             _ => return left,
         };
 
+        let span = self.span();
         self.eat(eat);
-        let right = self.parse_factor();
-        let bin_op_expression = BinOpExpression::new(left, right, operator);
-        let expression = self.parse_term_prime(Expression::BinOpExpression(bin_op_expression));
-        expression
+        let right = self.parse_cast();
+        let bin_op_expression = BinOpExpression {
+            meta: Meta::at(span),
+            ..BinOpExpression::new(left, right, operator)
+        };
+        self.parse_term_prime(Expression::BinOpExpression(bin_op_expression))
     }
 
     fn parse_expression_prime(&mut self, left: Expression) -> Expression {
@@ -478,24 +581,191 @@ impl Parser {
             _ => return left,
         };
 
+        let span = self.span();
         self.eat(eat);
         let right = self.parse_term();
-        let bin_op_expression = BinOpExpression::new(left, right, operator);
-        let expression =
-            self.parse_expression_prime(Expression::BinOpExpression(bin_op_expression));
-        expression
+        let bin_op_expression = BinOpExpression {
+            meta: Meta::at(span),
+            ..BinOpExpression::new(left, right, operator)
+        };
+        self.parse_expression_prime(Expression::BinOpExpression(bin_op_expression))
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use ast::build::*;
+    use lexer::Lexer;
 
     #[test]
     fn dummy() {
         let mut parser = Parser::new(Vec::new());
         parser.parse();
     }
+
+    fn parse(source: &str) -> Program {
+        let parser = Parser::new(Lexer::tokenise_source(source));
+        assert_eq!(parser.get_errors(), &Vec::<String>::new());
+        parser.get_program().unwrap().clone()
+    }
+
+    fn only_function(mut program: Program) -> FunctionDeclaration {
+        assert_eq!(program.declarations.len(), 1);
+        match program.declarations.remove(0) {
+            Declaration::FunctionDeclaration(function) => function,
+        }
+    }
+
+    fn parse_function(source: &str) -> FunctionDeclaration {
+        only_function(parse(source))
+    }
+
+    // With node ids and spans reset, to compare it to a hand built function
+    fn parse_structure(source: &str) -> FunctionDeclaration {
+        only_function(parse(source).without_meta())
+    }
+
+    fn parse_errors(source: &str) -> Vec<String> {
+        Parser::new(Lexer::tokenise_source(source))
+            .get_errors()
+            .clone()
+    }
+
+    #[test]
+    fn sample_program() {
+        let parsed = parse(
+            "fn print_int(a: u64) -> void {\n\n}\n\n\
+             fn main(a: u64) -> u64 {\n\tprint_int(a);\n\treturn a + 1;\n}\n",
+        );
+        let expected = program([
+            fn_decl("print_int").param("a", u64()).body([]),
+            fn_decl("main").param("a", u64()).returns(u64()).body([
+                expr(call("print_int", [var("a")])),
+                ret(add(var("a"), lit(1))),
+            ]),
+        ]);
+        assert_ne!(parsed, expected);
+        assert!(parsed.structurally_eq(&expected));
+        assert_eq!(parsed.without_meta(), expected.without_meta());
+    }
+
+    #[test]
+    fn multiplication_binds_tighter_than_addition() {
+        let function = parse_structure("fn f() -> u64 { return 1 + 2 * 3 - 4 % 5; }");
+        let expected = sub(add(lit(1), mul(lit(2), lit(3))), rem(lit(4), lit(5)));
+        assert_eq!(function.body, vec![ret(expected)]);
+    }
+
+    #[test]
+    fn operators_are_left_associative() {
+        let function = parse_structure("fn f() -> u64 { return a - b - c; return a / b * c; }");
+        assert_eq!(
+            function.body,
+            vec![
+                ret(sub(sub(var("a"), var("b")), var("c"))),
+                ret(mul(div(var("a"), var("b")), var("c"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        let function = parse_structure("fn f() -> u64 { return (1 + 2) * g(a, (b)); }");
+        let expected = mul(add(lit(1), lit(2)), call("g", [var("a"), var("b")]));
+        assert_eq!(function.body, vec![ret(expected)]);
+    }
+
+    #[test]
+    fn casts_bind_tighter_than_binary_operators() {
+        let function = parse_structure(
+            "fn f(a: u8, b: i64) -> u16 { return a * b as u8 + (a + 1) as u16 as u16 - g(a) as u16; }",
+        );
+        let expected = sub(
+            add(
+                mul(var("a"), cast(var("b"), u8())),
+                cast(cast(add(var("a"), lit(1)), u16()), u16()),
+            ),
+            cast(call("g", [var("a")]), u16()),
+        );
+        assert_eq!(function.body, vec![ret(expected)]);
+        assert_eq!(
+            parse_errors("fn f(a: u8) -> u16 { return a as 1; }")[0],
+            "Wrong token at 0:33, expected \"type\""
+        );
+    }
+
+    #[test]
+    fn else_if_chain() {
+        let function = parse_structure(
+            "fn f(a: u64) -> u64 { if a { return 1; } else if a - 1 { return 2; } else { return 3; } }",
+        );
+        let expected = if_(var("a"), [ret(lit(1))])
+            .else_if(sub(var("a"), lit(1)), [ret(lit(2))])
+            .else_([ret(lit(3))]);
+        assert_eq!(function.body, vec![expected]);
+    }
+
+    #[test]
+    fn declarations_assignments_and_loops() {
+        let function = parse_structure(
+            "fn f(n: i32, m: u8) -> void { let i: i32 = 0; while n - i { i = i + 1; } return; }",
+        );
+        let expected = fn_decl("f").param("n", i32()).param("m", u8()).body([
+            decl("i", i32(), lit(0)),
+            while_loop(
+                sub(var("n"), var("i")),
+                [assign("i", add(var("i"), lit(1)))],
+            ),
+            ret_void(),
+        ]);
+        assert_eq!(function, expected);
+    }
+
+    #[test]
+    fn cloned_subtrees_are_equal() {
+        let function = parse_function("fn f() -> u64 { return (1 + 2) * 3; }");
+        let copy = function.clone();
+        assert_eq!(function, copy);
+        assert_ne!(function.body, Vec::new());
+    }
+
+    #[test]
+    fn equality_sees_spans() {
+        let first = parse("fn f() -> void {}");
+        let moved = parse("\nfn f() -> void {}");
+        assert_ne!(first, moved);
+        assert!(first.structurally_eq(&moved));
+    }
+
+    #[test]
+    fn spans_and_node_ids() {
+        let function = parse_function("fn f(a: u64) -> u64 {\n    return a +\n        g(1);\n}");
+        assert_eq!(function.meta.span, Span::new(0, 3));
+        assert_eq!(function.meta.id, NodeId(1));
+        assert_eq!(function.parameters[0].meta.span, Span::new(0, 5));
+        assert_eq!(function.parameters[0].meta.id, NodeId(2));
+
+        let Statement::ReturnStatement(Some(Expression::BinOpExpression(sum)), meta) =
+            &function.body[0]
+        else {
+            panic!("expected a return of a sum");
+        };
+        assert_eq!(meta.span, Span::new(1, 4));
+        assert_eq!(sum.meta.span, Span::new(1, 13));
+        assert_eq!(sum.left.meta().span, Span::new(1, 11));
+        assert_eq!(sum.right.meta().span, Span::new(2, 8));
+        assert_eq!(sum.right.meta().id, NodeId(6));
+    }
+
+    #[test]
+    fn missing_semicolon_is_reported() {
+        let errors = parse_errors("fn f() -> u64 { return 1 }");
+        assert_eq!(
+            errors,
+            vec![String::from("Wrong token at 0:25, expected \"semicolon\"")]
+        );
+    }
 }
 
 /*
@@ -0,0 +1,64 @@
+use ast::*;
+
+// Functions every program can call without declaring them, implemented by the backends. They
+// resolve to definitions with ids counting down from u32::MAX, which never collide with the ids
+// of parsed nodes, and a program may not declare a function with the same name.
+//
+//     print_int(value: i64) -> void     prints value and a newline
+//     print_uint(value: u64) -> void    prints value and a newline
+//     print_char(c: u8) -> void         writes the byte c
+//     read_int() -> i64                 reads a line holding an integer, traps on anything else
+//     exit(status: i32) -> void         ends the program with the exit status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub parameters: &'static [(&'static str, SimpleType)],
+    pub return_type: SimpleType,
+}
+
+pub const BUILTINS: [Builtin; 5] = [
+    Builtin {
+        name: "print_int",
+        parameters: &[("value", SimpleType::I64)],
+        return_type: SimpleType::Void,
+    },
+    Builtin {
+        name: "print_uint",
+        parameters: &[("value", SimpleType::U64)],
+        return_type: SimpleType::Void,
+    },
+    Builtin {
+        name: "print_char",
+        parameters: &[("c", SimpleType::U8)],
+        return_type: SimpleType::Void,
+    },
+    Builtin {
+        name: "read_int",
+        parameters: &[],
+        return_type: SimpleType::I64,
+    },
+    Builtin {
+        name: "exit",
+        parameters: &[("status", SimpleType::I32)],
+        return_type: SimpleType::Void,
+    },
+];
+
+impl Builtin {
+    pub fn id(&self) -> NodeId {
+        let index = BUILTINS
+            .iter()
+            .position(|builtin| builtin.name == self.name)
+            .unwrap();
+        NodeId(u32::MAX - index as u32)
+    }
+
+    pub fn by_name(name: &str) -> Option<&'static Builtin> {
+        BUILTINS.iter().find(|builtin| builtin.name == name)
+    }
+
+    pub fn by_id(id: NodeId) -> Option<&'static Builtin> {
+        let index = u32::MAX.checked_sub(id.0)? as usize;
+        BUILTINS.get(index)
+    }
+}
@@ -0,0 +1,464 @@
+use std::collections::HashMap;
+
+use ast::*;
+
+pub mod builtins;
+pub mod symbols;
+
+use builtins::{BUILTINS, Builtin};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefinitionKind {
+    Builtin,
+    Function,
+    Parameter,
+    Local,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+    pub id: NodeId,
+    pub name: String,
+    pub kind: DefinitionKind,
+    pub span: Span,
+    pub scope: ScopeId,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ScopeId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeKind {
+    // Holds the functions, always ScopeId(0)
+    Global,
+    // Holds the parameters of a function
+    Function,
+    // Body of a function, if, else or while
+    Block,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scope {
+    pub id: ScopeId,
+    pub kind: ScopeKind,
+    pub parent: Option<ScopeId>,
+    // Where the function, if, else or while that opens the scope starts
+    pub span: Span,
+}
+
+// Links every name in a program to the node that declares it. Functions are visible everywhere,
+// parameters in the whole body, and a `let` from the next statement to the end of its block.
+// Blocks of if, else and while open a new scope, an inner `let` may shadow an outer name.
+pub struct Resolution {
+    definitions: HashMap<NodeId, Definition>,
+    // reference id -> definition id
+    references: HashMap<NodeId, NodeId>,
+    scopes: Vec<Scope>,
+    errors: Vec<String>,
+}
+
+impl Resolution {
+    pub fn new(program: &Program) -> Self {
+        let mut resolver = Resolver {
+            resolution: Resolution {
+                definitions: HashMap::new(),
+                references: HashMap::new(),
+                scopes: vec![Scope {
+                    id: ScopeId(0),
+                    kind: ScopeKind::Global,
+                    parent: None,
+                    span: Span::default(),
+                }],
+                errors: Vec::new(),
+            },
+            functions: HashMap::new(),
+            scopes: Vec::new(),
+        };
+        resolver.program(program);
+        resolver.resolution
+    }
+
+    pub fn get_errors(&self) -> &Vec<String> {
+        &self.errors
+    }
+
+    pub fn definition(&self, id: NodeId) -> Option<&Definition> {
+        self.definitions.get(&id)
+    }
+
+    // The definition a variable reference, assignment or call refers to
+    pub fn resolve(&self, reference: NodeId) -> Option<&Definition> {
+        self.references
+            .get(&reference)
+            .and_then(|definition| self.definitions.get(definition))
+    }
+
+    pub fn definitions(&self) -> impl Iterator<Item = &Definition> {
+        self.definitions.values()
+    }
+
+    pub fn scope(&self, id: ScopeId) -> &Scope {
+        &self.scopes[id.0 as usize]
+    }
+
+    pub fn scopes(&self) -> &Vec<Scope> {
+        &self.scopes
+    }
+
+    // Pairs of (reference id, definition id)
+    pub fn references(&self) -> impl Iterator<Item = (NodeId, NodeId)> + '_ {
+        self.references
+            .iter()
+            .map(|(reference, definition)| (*reference, *definition))
+    }
+}
+
+struct Resolver {
+    resolution: Resolution,
+    functions: HashMap<String, NodeId>,
+    // Innermost scope last, the global scope is not on the stack since functions live in their
+    // own namespace
+    scopes: Vec<(ScopeId, HashMap<String, NodeId>)>,
+}
+
+impl Resolver {
+    fn error(&mut self, message: String) {
+        self.resolution.errors.push(message);
+    }
+
+    fn define(&mut self, name: &str, kind: DefinitionKind, meta: &Meta) {
+        let scope = match kind {
+            DefinitionKind::Builtin | DefinitionKind::Function => ScopeId(0),
+            _ => {
+                let (scope, names) = self.scopes.last_mut().unwrap();
+                names.insert(name.to_string(), meta.id);
+                *scope
+            }
+        };
+        self.resolution.definitions.insert(
+            meta.id,
+            Definition {
+                id: meta.id,
+                name: name.to_string(),
+                kind,
+                span: meta.span,
+                scope,
+            },
+        );
+    }
+
+    fn push_scope(&mut self, kind: ScopeKind, span: Span) {
+        let id = ScopeId(self.resolution.scopes.len() as u32);
+        let parent = self.scopes.last().map_or(ScopeId(0), |(parent, _)| *parent);
+        self.resolution.scopes.push(Scope {
+            id,
+            kind,
+            parent: Some(parent),
+            span,
+        });
+        self.scopes.push((id, HashMap::new()));
+    }
+
+    fn program(&mut self, program: &Program) {
+        for builtin in &BUILTINS {
+            let meta = Meta {
+                id: builtin.id(),
+                span: Span::default(),
+            };
+            self.functions.insert(builtin.name.to_string(), meta.id);
+            self.define(builtin.name, DefinitionKind::Builtin, &meta);
+        }
+
+        // Functions may be called before they are declared, so collect them all first
+        for declaration in &program.declarations {
+            let Declaration::FunctionDeclaration(function) = declaration;
+            if Builtin::by_name(&function.name).is_some() {
+                self.error(format!(
+                    "Function \"{}\" at {} has the name of a builtin",
+                    function.name, function.meta.span
+                ));
+                continue;
+            }
+            if let Some(first) = self.functions.get(&function.name) {
+                let first = self.resolution.definitions[first].span;
+                self.error(format!(
+                    "Duplicate function \"{}\" at {}, first declared at {}",
+                    function.name, function.meta.span, first
+                ));
+                continue;
+            }
+            self.functions
+                .insert(function.name.clone(), function.meta.id);
+            self.define(&function.name, DefinitionKind::Function, &function.meta);
+        }
+
+        for declaration in &program.declarations {
+            let Declaration::FunctionDeclaration(function) = declaration;
+            self.function(function);
+        }
+    }
+
+    fn function(&mut self, function: &FunctionDeclaration) {
+        self.push_scope(ScopeKind::Function, function.meta.span);
+        for parameter in &function.parameters {
+            if self.scopes.last().unwrap().1.contains_key(&parameter.name) {
+                self.error(format!(
+                    "Duplicate parameter \"{}\" at {} in function \"{}\"",
+                    parameter.name, parameter.meta.span, function.name
+                ));
+                continue;
+            }
+            self.define(&parameter.name, DefinitionKind::Parameter, &parameter.meta);
+        }
+
+        self.body(&function.body, function.meta.span);
+        self.scopes.pop();
+    }
+
+    fn body(&mut self, body: &[Statement], span: Span) {
+        self.push_scope(ScopeKind::Block, span);
+        for statement in body {
+            self.statement(statement);
+        }
+        self.scopes.pop();
+    }
+
+    fn lookup_variable(&self, name: &str) -> Option<NodeId> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|(_, names)| names.get(name).copied())
+    }
+
+    fn reference_variable(&mut self, name: &str, meta: &Meta) {
+        match self.lookup_variable(name) {
+            Some(definition) => {
+                self.resolution.references.insert(meta.id, definition);
+            }
+            None => self.error(format!("Undefined variable \"{}\" at {}", name, meta.span)),
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::DeclarationStatement(declaration) => {
+                // The initialiser is resolved first, `let a: u64 = a;` refers to an outer a
+                self.expression(&declaration.value);
+                self.define(
+                    &declaration.variable,
+                    DefinitionKind::Local,
+                    &declaration.meta,
+                );
+            }
+            Statement::VariableAssignmentStatement(assignment) => {
+                self.expression(&assignment.value);
+                self.reference_variable(&assignment.variable, &assignment.meta);
+            }
+            Statement::IfStatement(if_statement) => {
+                let mut next = Some(if_statement);
+                while let Some(if_statement) = next {
+                    if let Some(condition) = &if_statement.condition {
+                        self.expression(condition);
+                    }
+                    self.body(&if_statement.body, if_statement.meta.span);
+                    next = if_statement.tail_conditions.as_deref();
+                }
+            }
+            Statement::WhileStatement(while_statement) => {
+                self.expression(&while_statement.condition);
+                self.body(&while_statement.body, while_statement.meta.span);
+            }
+            Statement::ReturnStatement(value, _) => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            Statement::Expression(expression) => self.expression(expression),
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::BinOpExpression(bin_op) => {
+                self.expression(&bin_op.left);
+                self.expression(&bin_op.right);
+            }
+            Expression::LiteralExpression(..) => {}
+            Expression::VariableReferenceExpression(name, meta) => {
+                self.reference_variable(name, meta)
+            }
+            Expression::FunctionCallExpression(call) => {
+                match self.functions.get(&call.name) {
+                    Some(definition) => {
+                        self.resolution.references.insert(call.meta.id, *definition);
+                    }
+                    None => self.error(format!(
+                        "Undefined function \"{}\" at {}",
+                        call.name, call.meta.span
+                    )),
+                }
+                for parameter in &call.parameters {
+                    self.expression(parameter);
+                }
+            }
+            Expression::CastExpression(cast) => self.expression(&cast.value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn parse(source: &str) -> Program {
+        let parser = Parser::new(Lexer::tokenise_source(source));
+        assert_eq!(parser.get_errors(), &Vec::<String>::new());
+        parser.get_program().unwrap().clone()
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        Resolution::new(&parse(source)).get_errors().clone()
+    }
+
+    #[test]
+    fn sample_program() {
+        let program = parse(
+            "fn show(a: u64) -> void {}\n\
+             fn main(a: u64) -> u64 {\n    show(a);\n    return a + 1;\n}",
+        );
+        let resolution = Resolution::new(&program);
+        assert_eq!(resolution.get_errors(), &Vec::<String>::new());
+
+        let Declaration::FunctionDeclaration(main) = &program.declarations[1];
+        let Statement::Expression(Expression::FunctionCallExpression(call)) = &main.body[0] else {
+            panic!("expected a call");
+        };
+        let callee = resolution.resolve(call.meta.id).unwrap();
+        assert_eq!(callee.kind, DefinitionKind::Function);
+        assert_eq!(callee.name, "show");
+        assert_eq!(callee.span, Span::new(0, 3));
+
+        let argument = resolution.resolve(call.parameters[0].meta().id).unwrap();
+        assert_eq!(argument.kind, DefinitionKind::Parameter);
+        assert_eq!(argument.id, main.parameters[0].meta.id);
+        assert_eq!(resolution.references().count(), 3);
+    }
+
+    #[test]
+    fn undefined_names() {
+        assert_eq!(
+            errors("fn main() -> u64 {\n    b = 1;\n    return f(a);\n}"),
+            vec![
+                String::from("Undefined variable \"b\" at 1:4"),
+                String::from("Undefined function \"f\" at 2:11"),
+                String::from("Undefined variable \"a\" at 2:13"),
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_functions_and_parameters() {
+        assert_eq!(
+            errors("fn f(a: u64, b: u8, a: i8) -> void {}\nfn f() -> void {}"),
+            vec![
+                String::from("Duplicate function \"f\" at 1:3, first declared at 0:3"),
+                String::from("Duplicate parameter \"a\" at 0:20 in function \"f\""),
+            ]
+        );
+    }
+
+    #[test]
+    fn blocks_open_scopes() {
+        assert_eq!(
+            errors(
+                "fn f(a: u64) -> u64 {\n\
+                 if a { let b: u64 = a; } else { let c: u64 = b; }\n\
+                 while a { let d: u64 = 1; d = d - 1; }\n\
+                 return b + c + d;\n\
+                 }"
+            ),
+            vec![
+                String::from("Undefined variable \"b\" at 1:45"),
+                String::from("Undefined variable \"b\" at 3:7"),
+                String::from("Undefined variable \"c\" at 3:11"),
+                String::from("Undefined variable \"d\" at 3:15"),
+            ]
+        );
+    }
+
+    #[test]
+    fn shadowing_and_initialisers() {
+        let program = parse(
+            "fn f(a: u64) -> u64 {\n\
+             let a: u64 = a + 1;\n\
+             if a { let a: u8 = 2; return a; }\n\
+             return a;\n\
+             }",
+        );
+        let resolution = Resolution::new(&program);
+        assert_eq!(resolution.get_errors(), &Vec::<String>::new());
+
+        let Declaration::FunctionDeclaration(function) = &program.declarations[0];
+        let Statement::DeclarationStatement(outer) = &function.body[0] else {
+            panic!("expected a declaration");
+        };
+        let Expression::BinOpExpression(initialiser) = &outer.value else {
+            panic!("expected a sum");
+        };
+        let initialiser = resolution.resolve(initialiser.left.meta().id).unwrap();
+        assert_eq!(initialiser.kind, DefinitionKind::Parameter);
+
+        let Statement::IfStatement(if_statement) = &function.body[1] else {
+            panic!("expected an if statement");
+        };
+        let Statement::ReturnStatement(Some(inner_return), _) = &if_statement.body[1] else {
+            panic!("expected a return");
+        };
+        let inner = resolution.resolve(inner_return.meta().id).unwrap();
+        assert_eq!(inner.span, Span::new(2, 11));
+
+        let Statement::ReturnStatement(Some(outer_return), _) = &function.body[2] else {
+            panic!("expected a return");
+        };
+        assert_eq!(
+            resolution.resolve(outer_return.meta().id).unwrap().id,
+            outer.meta.id
+        );
+    }
+
+    #[test]
+    fn builtins_are_always_defined() {
+        let program = parse("fn main() -> void { print_int(read_int()); }");
+        let resolution = Resolution::new(&program);
+        assert_eq!(resolution.get_errors(), &Vec::<String>::new());
+
+        let Declaration::FunctionDeclaration(main) = &program.declarations[0];
+        let Statement::Expression(Expression::FunctionCallExpression(call)) = &main.body[0] else {
+            panic!("expected a call");
+        };
+        let print_int = resolution.resolve(call.meta.id).unwrap();
+        assert_eq!(print_int.kind, DefinitionKind::Builtin);
+        assert_eq!(Builtin::by_id(print_int.id).unwrap().name, "print_int");
+        let read_int = resolution.resolve(call.parameters[0].meta().id).unwrap();
+        assert_eq!(Builtin::by_id(read_int.id), Builtin::by_name("read_int"));
+
+        assert_eq!(
+            errors("fn exit(status: i32) -> void {}\nfn main() -> void { exit(1); }"),
+            vec![String::from(
+                "Function \"exit\" at 0:3 has the name of a builtin"
+            )]
+        );
+    }
+
+    #[test]
+    fn functions_are_visible_before_their_declaration() {
+        assert_eq!(
+            errors(
+                "fn main() -> u64 { return twice(2); }\nfn twice(n: u64) -> u64 { return n * 2; }"
+            ),
+            Vec::<String>::new()
+        );
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+
+use ast::*;
+
+use crate::{Resolution, ScopeId, builtins::BUILTINS};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    // Parameter names and types in declaration order
+    pub parameters: Vec<(String, Type)>,
+    pub return_type: Type,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function(Signature),
+    Parameter(Type),
+    Local(Type),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub id: NodeId,
+    pub name: String,
+    pub kind: SymbolKind,
+    pub scope: ScopeId,
+    pub span: Span,
+}
+
+impl Symbol {
+    // Type of the value, the return type for functions
+    pub fn datatype(&self) -> Type {
+        match &self.kind {
+            SymbolKind::Function(signature) => signature.return_type,
+            SymbolKind::Parameter(datatype) | SymbolKind::Local(datatype) => *datatype,
+        }
+    }
+}
+
+// A place in the source where a name is written, either its definition or a use of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Occurrence {
+    span: Span,
+    length: usize,
+    definition: NodeId,
+}
+
+// Everything a program declares with its type, built on top of the Resolution. Names that did
+// not resolve have no symbol, their errors are in get_errors.
+pub struct SymbolTable {
+    resolution: Resolution,
+    symbols: HashMap<NodeId, Symbol>,
+    // Definition ids in source order
+    order: Vec<NodeId>,
+    occurrences: Vec<Occurrence>,
+}
+
+impl SymbolTable {
+    pub fn new(program: &Program) -> Self {
+        let mut table = SymbolTable {
+            resolution: Resolution::new(program),
+            symbols: HashMap::new(),
+            order: Vec::new(),
+            occurrences: Vec::new(),
+        };
+        // Builtins can be looked up like any function but are not part of symbols()
+        for builtin in &BUILTINS {
+            let signature = Signature {
+                parameters: builtin
+                    .parameters
+                    .iter()
+                    .map(|(name, datatype)| (name.to_string(), Type::SimpleType(*datatype)))
+                    .collect(),
+                return_type: Type::SimpleType(builtin.return_type),
+            };
+            let symbol = Symbol {
+                id: builtin.id(),
+                name: builtin.name.to_string(),
+                kind: SymbolKind::Function(signature),
+                scope: ScopeId(0),
+                span: Span::default(),
+            };
+            table.symbols.insert(symbol.id, symbol);
+        }
+        for declaration in &program.declarations {
+            let Declaration::FunctionDeclaration(function) = declaration;
+            table.function(function);
+        }
+        table
+    }
+
+    pub fn get_errors(&self) -> &Vec<String> {
+        self.resolution.get_errors()
+    }
+
+    pub fn resolution(&self) -> &Resolution {
+        &self.resolution
+    }
+
+    // Symbols in the order they are declared in the source
+    pub fn symbols(&self) -> impl Iterator<Item = &Symbol> {
+        self.order.iter().map(|id| &self.symbols[id])
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols()
+            .filter(|symbol| matches!(symbol.kind, SymbolKind::Function(_)))
+    }
+
+    // Takes the id of a declaration or of a reference, assignment or call
+    pub fn symbol(&self, id: NodeId) -> Option<&Symbol> {
+        self.symbols.get(&id).or_else(|| {
+            self.resolution
+                .resolve(id)
+                .and_then(|definition| self.symbols.get(&definition.id))
+        })
+    }
+
+    // The symbol whose name covers the given position, for a declaration or a use
+    pub fn symbol_at(&self, position: Span) -> Option<&Symbol> {
+        self.occurrences
+            .iter()
+            .find(|occurrence| {
+                occurrence.span.line == position.line
+                    && occurrence.span.column <= position.column
+                    && position.column < occurrence.span.column + occurrence.length
+            })
+            .and_then(|occurrence| self.symbols.get(&occurrence.definition))
+    }
+
+    fn add(&mut self, name: &str, kind: SymbolKind, meta: &Meta) {
+        // Duplicates are left out by the resolver, so keep the first declaration
+        let Some(definition) = self.resolution.definition(meta.id) else {
+            return;
+        };
+        let symbol = Symbol {
+            id: meta.id,
+            name: name.to_string(),
+            kind,
+            scope: definition.scope,
+            span: meta.span,
+        };
+        self.occur(name, meta, meta.id);
+        self.order.push(meta.id);
+        self.symbols.insert(meta.id, symbol);
+    }
+
+    fn occur(&mut self, name: &str, meta: &Meta, definition: NodeId) {
+        self.occurrences.push(Occurrence {
+            span: meta.span,
+            length: name.len(),
+            definition,
+        });
+    }
+
+    fn reference(&mut self, name: &str, meta: &Meta) {
+        if let Some(definition) = self.resolution.resolve(meta.id) {
+            let definition = definition.id;
+            self.occur(name, meta, definition);
+        }
+    }
+
+    fn function(&mut self, function: &FunctionDeclaration) {
+        let signature = Signature {
+            parameters: function
+                .parameters
+                .iter()
+                .map(|parameter| (parameter.name.clone(), parameter.datatype))
+                .collect(),
+            return_type: function.return_type,
+        };
+        self.add(
+            &function.name,
+            SymbolKind::Function(signature),
+            &function.meta,
+        );
+        for parameter in &function.parameters {
+            self.add(
+                &parameter.name,
+                SymbolKind::Parameter(parameter.datatype),
+                &parameter.meta,
+            );
+        }
+        self.body(&function.body);
+    }
+
+    fn body(&mut self, body: &[Statement]) {
+        for statement in body {
+            match statement {
+                Statement::DeclarationStatement(declaration) => {
+                    self.expression(&declaration.value);
+                    self.add(
+                        &declaration.variable,
+                        SymbolKind::Local(declaration.datatype),
+                        &declaration.meta,
+                    );
+                }
+                Statement::VariableAssignmentStatement(assignment) => {
+                    self.reference(&assignment.variable, &assignment.meta);
+                    self.expression(&assignment.value);
+                }
+                Statement::IfStatement(if_statement) => {
+                    let mut next = Some(if_statement);
+                    while let Some(if_statement) = next {
+                        if let Some(condition) = &if_statement.condition {
+                            self.expression(condition);
+                        }
+                        self.body(&if_statement.body);
+                        next = if_statement.tail_conditions.as_deref();
+                    }
+                }
+                Statement::WhileStatement(while_statement) => {
+                    self.expression(&while_statement.condition);
+                    self.body(&while_statement.body);
+                }
+                Statement::ReturnStatement(value, _) => {
+                    if let Some(value) = value {
+                        self.expression(value);
+                    }
+                }
+                Statement::Expression(expression) => self.expression(expression),
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::BinOpExpression(bin_op) => {
+                self.expression(&bin_op.left);
+                self.expression(&bin_op.right);
+            }
+            Expression::LiteralExpression(..) => {}
+            Expression::VariableReferenceExpression(name, meta) => self.reference(name, meta),
+            Expression::FunctionCallExpression(call) => {
+                self.reference(&call.name, &call.meta);
+                for parameter in &call.parameters {
+                    self.expression(parameter);
+                }
+            }
+            Expression::CastExpression(cast) => self.expression(&cast.value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ScopeKind;
+    use ast::build;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn table(source: &str) -> (Program, SymbolTable) {
+        let parser = Parser::new(Lexer::tokenise_source(source));
+        assert_eq!(parser.get_errors(), &Vec::<String>::new());
+        let program = parser.get_program().unwrap().clone();
+        let table = SymbolTable::new(&program);
+        assert_eq!(table.get_errors(), &Vec::<String>::new());
+        (program, table)
+    }
+
+    #[test]
+    fn lists_functions_parameters_and_locals() {
+        let (_, table) = table(
+            "fn add(a: u8, b: i32) -> i64 {\n    let c: i64 = a + b;\n    return c;\n}\n\
+             fn main() -> void { while 1 { let d: u16 = add(1, 2); } }",
+        );
+        let symbols: Vec<(&str, Type, Span)> = table
+            .symbols()
+            .map(|symbol| (symbol.name.as_str(), symbol.datatype(), symbol.span))
+            .collect();
+        assert_eq!(
+            symbols,
+            vec![
+                ("add", build::i64(), Span::new(0, 3)),
+                ("a", build::u8(), Span::new(0, 7)),
+                ("b", build::i32(), Span::new(0, 14)),
+                ("c", build::i64(), Span::new(1, 8)),
+                ("main", build::void(), Span::new(4, 3)),
+                ("d", build::u16(), Span::new(4, 34)),
+            ]
+        );
+
+        let add = table.functions().next().unwrap();
+        assert_eq!(
+            add.kind,
+            SymbolKind::Function(Signature {
+                parameters: vec![
+                    (String::from("a"), build::u8()),
+                    (String::from("b"), build::i32()),
+                ],
+                return_type: build::i64(),
+            })
+        );
+        assert_eq!(table.functions().count(), 2);
+    }
+
+    #[test]
+    fn scopes_nest() {
+        let (_, table) = table("fn f(a: u64) -> void { if a { let b: u64 = a; } }");
+        let resolution = table.resolution();
+        let a = table.symbols().find(|symbol| symbol.name == "a").unwrap();
+        let b = table.symbols().find(|symbol| symbol.name == "b").unwrap();
+        let f = table.functions().next().unwrap();
+
+        assert_eq!(f.scope, ScopeId(0));
+        assert_eq!(resolution.scope(a.scope).kind, ScopeKind::Function);
+        assert_eq!(resolution.scope(b.scope).kind, ScopeKind::Block);
+        assert_eq!(resolution.scope(b.scope).span, Span::new(0, 23));
+
+        // if block -> function body -> parameters -> global
+        let body = resolution.scope(b.scope).parent.unwrap();
+        assert_eq!(resolution.scope(body).parent, Some(a.scope));
+        assert_eq!(resolution.scope(a.scope).parent, Some(ScopeId(0)));
+    }
+
+    #[test]
+    fn lookups_by_node_id_and_position() {
+        let (program, table) = table(
+            "fn twice(n: u32) -> u32 { return n * 2; }\n\
+             fn main() -> u32 {\n    let x: u32 = twice(3);\n    x = x + 1;\n    return x;\n}",
+        );
+        let Declaration::FunctionDeclaration(main) = &program.declarations[1];
+        let Statement::DeclarationStatement(x) = &main.body[0] else {
+            panic!("expected a declaration");
+        };
+        let Expression::FunctionCallExpression(call) = &x.value else {
+            panic!("expected a call");
+        };
+
+        assert_eq!(table.symbol(x.meta.id).unwrap().name, "x");
+        assert_eq!(table.symbol(call.meta.id).unwrap().name, "twice");
+        assert_eq!(table.symbol(main.body[2].meta().id), None);
+
+        let at = |line, column| table.symbol_at(Span::new(line, column)).map(|s| s.id);
+        // Both ends of `twice` in the call, then the parameter n at its use
+        assert!(at(0, 3).is_some());
+        assert_eq!(at(2, 17), at(0, 3));
+        assert_eq!(at(2, 21), at(0, 3));
+        assert_eq!(at(2, 22), None);
+        assert_eq!(at(0, 33), at(0, 9));
+        assert_eq!(table.symbol_at(Span::new(0, 9)).unwrap().name, "n");
+        // Assigned and read x
+        assert_eq!(at(3, 4), Some(x.meta.id));
+        assert_eq!(at(3, 8), Some(x.meta.id));
+        assert_eq!(at(4, 11), Some(x.meta.id));
+        assert_eq!(at(3, 6), None);
+    }
+}
@@ -1,12 +1,256 @@
+use std::{env, process};
+
+use analysis::{
+    entry::{EntryCheck, EntryPoint},
+    lints::{Lint, Lints},
+    returns::ReturnCheck,
+};
+use ast::{
+    Program,
+    arena::{ArenaProgram, boxed_heap_bytes},
+};
+use interp::Interpreter;
+use ir::{Module, pipeline::Failure};
 use lexer::{self, Lexer};
 use parser::{self, Parser};
+use typeck::TypeCheck;
+
+// What the driver prints after running the frontend
+enum Emit {
+    Debug,
+    TokensJson,
+    AstJson,
+    AstDot,
+    // Size of the parsed program as a boxed tree and lowered into the arena
+    AstStats,
+    Ir,
+    CfgDot,
+}
+
+struct Options {
+    // `compiler-rs run file args...` interprets the program instead of printing anything
+    run: bool,
+    emit: Emit,
+    // Lints silenced with --allow=name
+    allow: Vec<Lint>,
+    // Run the IR verifier after every pass, --verify-ir
+    verify_ir: bool,
+    path: String,
+    // Passed on to the program when running it
+    args: Vec<String>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = env::args().skip(1).peekable();
+    let run = args.next_if(|arg| arg == "run").is_some();
+    let mut emit = Emit::Debug;
+    let mut allow = Vec::new();
+    let mut verify_ir = false;
+    let mut path = None;
+    let mut program_args = Vec::new();
+
+    for arg in args {
+        if path.is_some() && run {
+            program_args.push(arg);
+        } else if let Some(kind) = arg.strip_prefix("--emit=") {
+            if run {
+                return Err(String::from("--emit cannot be combined with run"));
+            }
+            emit = match kind {
+                "tokens-json" => Emit::TokensJson,
+                "ast-json" => Emit::AstJson,
+                "ast-dot" => Emit::AstDot,
+                "ast-stats" => Emit::AstStats,
+                "ir" => Emit::Ir,
+                "cfg-dot" => Emit::CfgDot,
+                _ => return Err(format!("unknown emit kind \"{}\"", kind)),
+            };
+        } else if let Some(name) = arg.strip_prefix("--allow=") {
+            match Lint::from_name(name) {
+                Some(lint) => allow.push(lint),
+                None => return Err(format!("unknown lint \"{}\"", name)),
+            }
+        } else if arg == "--verify-ir" {
+            if run {
+                return Err(String::from("--verify-ir cannot be combined with run"));
+            }
+            verify_ir = true;
+        } else if arg.starts_with("--") {
+            return Err(format!("unknown option \"{}\"", arg));
+        } else if path.is_none() {
+            path = Some(arg);
+        } else {
+            return Err(format!("unexpected argument \"{}\"", arg));
+        }
+    }
+
+    if run && path.is_none() {
+        return Err(String::from("run needs a file"));
+    }
+
+    Ok(Options {
+        run,
+        emit,
+        allow,
+        verify_ir,
+        path: path.unwrap_or_else(|| String::from("./code")),
+        args: program_args,
+    })
+}
+
+// Runs every check on the parsed program and prints what they find. Returns the program with
+// its literals typed, ready for the backends, if there were no errors
+fn check(program: &Program, allow: &[Lint]) -> Option<(Program, EntryPoint)> {
+    let checker = TypeCheck::new(program);
+    let returns = ReturnCheck::new(program);
+    let entry = EntryCheck::new(program);
+    let errors = [
+        checker.symbols().get_errors(),
+        checker.get_errors(),
+        returns.get_errors(),
+        entry.get_errors(),
+    ];
+    for error in errors.iter().copied().flatten() {
+        eprintln!("{}", error);
+    }
+    for warning in Lints::new(program, allow).get_warnings() {
+        eprintln!("warning: {}", warning);
+    }
+    if errors.iter().any(|errors| !errors.is_empty()) {
+        return None;
+    }
+
+    let mut typed = program.clone();
+    checker.rewrite_literals(&mut typed);
+    Some((typed, *entry.entry().unwrap()))
+}
+
+// The checked program, exiting if it could not be parsed or failed a check
+fn checked_program(parser: &Parser, allow: &[Lint]) -> (Program, EntryPoint) {
+    let Some(program) = parser
+        .get_program()
+        .filter(|_| parser.get_errors().is_empty())
+    else {
+        process::exit(1);
+    };
+    check(program, allow).unwrap_or_else(|| process::exit(1))
+}
+
+// The IR of a checked program, exiting if the passes found errors in it or broke it
+fn compile(program: &Program, verify: bool) -> Module {
+    match ir::pipeline::compile(program, verify) {
+        Ok(module) => module,
+        Err(Failure::Errors(errors)) => {
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            process::exit(1);
+        }
+        Err(Failure::InvalidIr(error)) => {
+            eprintln!("error: {}", error);
+            process::exit(101);
+        }
+    }
+}
 
 fn main() {
-    let mut lexer = Lexer::new("./code");
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            eprintln!(
+                "usage: compiler-rs [--emit=tokens-json|ast-json|ast-dot|ast-stats|ir|cfg-dot] [--verify-ir] [--allow=lint]... [file]"
+            );
+            eprintln!("       compiler-rs run [--allow=lint]... file [args]...");
+            process::exit(2);
+        }
+    };
+
+    let mut lexer = Lexer::new(&options.path);
     lexer.tokenise();
 
-    println!("{:?}", lexer);
+    if let Emit::TokensJson = options.emit {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&lexer.get_tokens()).unwrap()
+        );
+        return;
+    }
+
+    if let (Emit::Debug, false) = (&options.emit, options.run) {
+        println!("{:?}", lexer);
+    }
+
+    let parser = Parser::new(lexer.get_tokens());
+    for error in parser.get_errors() {
+        eprintln!("{}", error);
+    }
+
+    if options.run {
+        let (program, entry) = checked_program(&parser, &options.allow);
+        // The program still runs on the AST, compiling it only reports the errors the passes find
+        compile(&program, false);
+        // Like argv in C the file counts as the first argument
+        let argc = 1 + options.args.len();
+        match Interpreter::new(&program).run(&entry, argc) {
+            Ok(status) => process::exit(status),
+            Err(error) => {
+                eprintln!("error: {}", error);
+                process::exit(101);
+            }
+        }
+    }
 
-    let mut parser = Parser::new(lexer.get_tokens());
-    println!("{:#?}", parser);
+    match options.emit {
+        Emit::AstJson => {
+            if !parser.get_errors().is_empty() {
+                process::exit(1);
+            }
+            let program = parser.get_program().unwrap();
+            println!("{}", serde_json::to_string_pretty(program).unwrap());
+        }
+        Emit::AstDot => {
+            if !parser.get_errors().is_empty() {
+                process::exit(1);
+            }
+            print!(
+                "{}",
+                ast::dot::program_to_dot(parser.get_program().unwrap())
+            );
+        }
+        Emit::AstStats => {
+            if !parser.get_errors().is_empty() {
+                process::exit(1);
+            }
+            let program = parser.get_program().unwrap();
+            // Keeps the symbols the lexer gave the identifiers
+            let mut interner = lexer.take_interner();
+            let arena = ArenaProgram::lower(program, &mut interner);
+            println!(
+                "{} statements, {} expressions, {} distinct strings",
+                arena.arena.statement_count(),
+                arena.arena.expression_count(),
+                interner.len()
+            );
+            println!("boxed {} bytes", boxed_heap_bytes(program));
+            println!("arena {} bytes", arena.heap_bytes() + interner.heap_bytes());
+        }
+        Emit::Ir => {
+            let (program, _) = checked_program(&parser, &options.allow);
+            print!("{}", compile(&program, options.verify_ir));
+        }
+        Emit::CfgDot => {
+            let (program, _) = checked_program(&parser, &options.allow);
+            print!(
+                "{}",
+                ir::cfg::module_to_dot(&compile(&program, options.verify_ir))
+            );
+        }
+        _ => {
+            println!("{:#?}", parser);
+            if let Some(program) = parser.get_program() {
+                check(program, &options.allow);
+            }
+        }
+    }
 }
@@ -0,0 +1,673 @@
+use std::collections::HashMap;
+
+use ast::*;
+use resolve::symbols::{SymbolKind, SymbolTable};
+
+// Gives every expression a type and checks that declarations, assignments, call arguments and
+// returns agree with the declared types. There are no implicit conversions, both operands of a
+// binary operator and the condition of an if or while must be integers, and a call to a void
+// function can only be used as a statement. Names that did not resolve have no type and are
+// not checked any further, their errors come from the symbol table.
+//
+// The source has no literal suffixes, so every integer literal takes its type from where it is
+// used: the declared type of a let or an assigned variable, the parameter it is passed to, the
+// return type, or the other operand of a binary operator. Literals without any such context,
+// like in `while 1` or `1 + 2;`, default to i64. rewrite_literals stores the result in the tree.
+// Expressions made of literals only are folded, and every literal and intermediate result has
+// to fit the inferred type, so `let x: u8 = 255 + 1;` is rejected.
+//
+// `value as type` converts between any two integer types, see SimpleType::wrap for how.
+pub struct TypeCheck {
+    symbols: SymbolTable,
+    types: HashMap<NodeId, Type>,
+    errors: Vec<String>,
+}
+
+impl TypeCheck {
+    pub fn new(program: &Program) -> Self {
+        let mut checker = TypeCheck {
+            symbols: SymbolTable::new(program),
+            types: HashMap::new(),
+            errors: Vec::new(),
+        };
+        for declaration in &program.declarations {
+            let Declaration::FunctionDeclaration(function) = declaration;
+            for parameter in &function.parameters {
+                if parameter.datatype == VOID {
+                    checker.error(format!(
+                        "Parameter \"{}\" at {} cannot have type void",
+                        parameter.name, parameter.meta.span
+                    ));
+                }
+            }
+            checker.body(&function.body, function.return_type);
+        }
+        checker
+    }
+
+    pub fn get_errors(&self) -> &Vec<String> {
+        &self.errors
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    // Type of an expression, None if it refers to an unresolved name
+    pub fn type_of(&self, expression: &Expression) -> Option<Type> {
+        self.types.get(&expression.meta().id).copied()
+    }
+
+    // Replaces the variant of every literal by its inferred type
+    pub fn rewrite_literals(&self, program: &mut Program) {
+        for declaration in &mut program.declarations {
+            let Declaration::FunctionDeclaration(function) = declaration;
+            self.rewrite_body(&mut function.body);
+        }
+    }
+
+    fn rewrite_body(&self, body: &mut [Statement]) {
+        for statement in body {
+            match statement {
+                Statement::DeclarationStatement(declaration) => {
+                    self.rewrite_expression(&mut declaration.value)
+                }
+                Statement::VariableAssignmentStatement(assignment) => {
+                    self.rewrite_expression(&mut assignment.value)
+                }
+                Statement::IfStatement(if_statement) => {
+                    let mut next = Some(if_statement);
+                    while let Some(if_statement) = next {
+                        if let Some(condition) = &mut if_statement.condition {
+                            self.rewrite_expression(condition);
+                        }
+                        self.rewrite_body(&mut if_statement.body);
+                        next = if_statement.tail_conditions.as_deref_mut();
+                    }
+                }
+                Statement::WhileStatement(while_statement) => {
+                    self.rewrite_expression(&mut while_statement.condition);
+                    self.rewrite_body(&mut while_statement.body);
+                }
+                Statement::ReturnStatement(value, _) => {
+                    if let Some(value) = value {
+                        self.rewrite_expression(value);
+                    }
+                }
+                Statement::Expression(expression) => self.rewrite_expression(expression),
+            }
+        }
+    }
+
+    fn rewrite_expression(&self, expression: &mut Expression) {
+        match expression {
+            Expression::BinOpExpression(bin_op) => {
+                self.rewrite_expression(&mut bin_op.left);
+                self.rewrite_expression(&mut bin_op.right);
+            }
+            Expression::LiteralExpression(literal, meta) => {
+                if let Some(Type::SimpleType(datatype)) = self.types.get(&meta.id) {
+                    let value = std::mem::take(literal.value_mut());
+                    *literal = LiteralExpression::new(*datatype, value);
+                }
+            }
+            Expression::VariableReferenceExpression(..) => {}
+            Expression::FunctionCallExpression(call) => {
+                for parameter in &mut call.parameters {
+                    self.rewrite_expression(parameter);
+                }
+            }
+            Expression::CastExpression(cast) => self.rewrite_expression(&mut cast.value),
+        }
+    }
+
+    fn error(&mut self, message: String) {
+        self.errors.push(message);
+    }
+
+    fn expect(&mut self, expected: Type, found: Option<Type>, span: Span) {
+        if let Some(found) = found
+            && found != expected
+        {
+            self.error(format!(
+                "Mismatched types at {}, expected {} but found {}",
+                span, expected, found
+            ));
+        }
+    }
+
+    fn body(&mut self, body: &[Statement], return_type: Type) {
+        for statement in body {
+            self.statement(statement, return_type);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement, return_type: Type) {
+        match statement {
+            Statement::DeclarationStatement(declaration) => {
+                let found = self.value(&declaration.value, Some(declaration.datatype));
+                if declaration.datatype == VOID {
+                    self.error(format!(
+                        "Variable \"{}\" at {} cannot have type void",
+                        declaration.variable, declaration.meta.span
+                    ));
+                    return;
+                }
+                self.expect(declaration.datatype, found, declaration.value.meta().span);
+            }
+            Statement::VariableAssignmentStatement(assignment) => {
+                let expected = self
+                    .symbols
+                    .symbol(assignment.meta.id)
+                    .map(|variable| variable.datatype());
+                let found = self.value(&assignment.value, expected);
+                if let Some(expected) = expected {
+                    self.expect(expected, found, assignment.value.meta().span);
+                }
+            }
+            Statement::IfStatement(if_statement) => {
+                let mut next = Some(if_statement);
+                while let Some(if_statement) = next {
+                    if let Some(condition) = &if_statement.condition {
+                        self.value(condition, None);
+                    }
+                    self.body(&if_statement.body, return_type);
+                    next = if_statement.tail_conditions.as_deref();
+                }
+            }
+            Statement::WhileStatement(while_statement) => {
+                self.value(&while_statement.condition, None);
+                self.body(&while_statement.body, return_type);
+            }
+            Statement::ReturnStatement(value, _) => {
+                // A value returned from a void function is reported by the return check
+                if let Some(value) = value {
+                    let found = self.value(value, Some(return_type));
+                    if return_type != VOID {
+                        self.expect(return_type, found, value.meta().span);
+                    }
+                }
+            }
+            Statement::Expression(expression) => {
+                self.expression(expression, None);
+            }
+        }
+    }
+
+    // An expression whose result is used, which rules out void
+    fn value(&mut self, expression: &Expression, hint: Option<Type>) -> Option<Type> {
+        let datatype = self.expression(expression, hint)?;
+        if datatype == VOID {
+            // Variables and parameters declared void are reported at their declaration too
+            self.error(match expression {
+                Expression::FunctionCallExpression(call) => format!(
+                    "Function \"{}\" returns void and cannot be used as a value at {}",
+                    call.name, call.meta.span
+                ),
+                Expression::VariableReferenceExpression(name, meta) => format!(
+                    "Variable \"{}\" has type void and cannot be used as a value at {}",
+                    name, meta.span
+                ),
+                expression => format!(
+                    "void cannot be used as a value at {}",
+                    expression.meta().span
+                ),
+            });
+            return None;
+        }
+        Some(datatype)
+    }
+
+    // The hint is the type the context expects, only literals make use of it
+    fn expression(&mut self, expression: &Expression, hint: Option<Type>) -> Option<Type> {
+        let datatype = match expression {
+            Expression::BinOpExpression(bin_op) if !is_untyped(expression) => {
+                // A side made of literals only takes the type of the other side
+                let (left, right) = if is_untyped(&bin_op.left) && !is_untyped(&bin_op.right) {
+                    let right = self.value(&bin_op.right, hint);
+                    (self.value(&bin_op.left, right.or(hint)), right)
+                } else {
+                    let left = self.value(&bin_op.left, hint);
+                    (left, self.value(&bin_op.right, left.or(hint)))
+                };
+                match (left, right) {
+                    (Some(left), Some(right)) if left != right => {
+                        self.error(format!(
+                            "Mismatched operands of \"{}\" at {}, {} and {}",
+                            bin_op.op, bin_op.meta.span, left, right
+                        ));
+                        None
+                    }
+                    _ => left.or(right),
+                }
+            }
+            Expression::BinOpExpression(_) | Expression::LiteralExpression(..) => {
+                let datatype = match hint {
+                    Some(Type::SimpleType(hint)) if hint != SimpleType::Void => hint,
+                    _ => SimpleType::I64,
+                };
+                self.constant(expression, datatype);
+                Some(Type::SimpleType(datatype))
+            }
+            Expression::VariableReferenceExpression(_, meta) => self
+                .symbols
+                .symbol(meta.id)
+                .map(|variable| variable.datatype()),
+            Expression::FunctionCallExpression(call) => self.call(call),
+            Expression::CastExpression(cast) => {
+                // Any integer converts to any other, a literal operand is an i64
+                self.value(&cast.value, None);
+                if cast.datatype == VOID {
+                    self.error(format!("Cannot cast to void at {}", cast.meta.span));
+                    return None;
+                }
+                Some(cast.datatype)
+            }
+        }?;
+        self.types.insert(expression.meta().id, datatype);
+        Some(datatype)
+    }
+
+    // Types and folds an expression of literals only, None when it does not fit or divides by
+    // zero, which is left for later passes to report
+    fn constant(&mut self, expression: &Expression, datatype: SimpleType) -> Option<i128> {
+        self.types
+            .insert(expression.meta().id, Type::SimpleType(datatype));
+        let (min, max) = datatype.range().unwrap();
+        match expression {
+            Expression::LiteralExpression(literal, meta) => {
+                let value = literal
+                    .value()
+                    .parse::<i128>()
+                    .ok()
+                    .filter(|value| (min..=max).contains(value));
+                if value.is_none() {
+                    self.error(format!(
+                        "Literal {} at {} is out of range for {}, valid range is {}..={}",
+                        literal.value(),
+                        meta.span,
+                        datatype,
+                        min,
+                        max
+                    ));
+                }
+                value
+            }
+            Expression::BinOpExpression(bin_op) => {
+                let left = self.constant(&bin_op.left, datatype);
+                let right = self.constant(&bin_op.right, datatype);
+                let (left, right) = (left?, right?);
+                let value = match bin_op.op {
+                    Operator::Plus => left.checked_add(right),
+                    Operator::Min => left.checked_sub(right),
+                    Operator::Mul => left.checked_mul(right),
+                    Operator::Div => left.checked_div(right),
+                    Operator::Mod => left.checked_rem(right),
+                };
+                // Operands are in range, so only a zero divisor or a result too big for an
+                // i128 has no value
+                let Some(value) = value else {
+                    if right != 0 {
+                        self.error(format!(
+                            "Constant expression at {} overflows, out of range for {}, valid range is {}..={}",
+                            bin_op.meta.span, datatype, min, max
+                        ));
+                    }
+                    return None;
+                };
+                if !(min..=max).contains(&value) {
+                    self.error(format!(
+                        "Constant expression at {} evaluates to {}, out of range for {}, valid range is {}..={}",
+                        bin_op.meta.span, value, datatype, min, max
+                    ));
+                    return None;
+                }
+                Some(value)
+            }
+            _ => unreachable!("constants are made of literals only"),
+        }
+    }
+
+    fn call(&mut self, call: &FunctionCallExpression) -> Option<Type> {
+        let signature = match self.symbols.symbol(call.meta.id).map(|callee| &callee.kind) {
+            Some(SymbolKind::Function(signature)) => Some(signature.clone()),
+            _ => None,
+        };
+        let arguments: Vec<Option<Type>> = call
+            .parameters
+            .iter()
+            .enumerate()
+            .map(|(i, argument)| {
+                let hint = signature
+                    .as_ref()
+                    .and_then(|signature| signature.parameters.get(i))
+                    .map(|(_, datatype)| *datatype);
+                self.value(argument, hint)
+            })
+            .collect();
+        let signature = signature?;
+
+        if arguments.len() != signature.parameters.len() {
+            self.error(format!(
+                "Function \"{}\" takes {} arguments but {} were given at {}",
+                call.name,
+                signature.parameters.len(),
+                arguments.len(),
+                call.meta.span
+            ));
+        } else {
+            for (i, ((_, expected), found)) in
+                signature.parameters.iter().zip(arguments).enumerate()
+            {
+                self.expect(*expected, found, call.parameters[i].meta().span);
+            }
+        }
+        Some(signature.return_type)
+    }
+}
+
+const VOID: Type = Type::SimpleType(SimpleType::Void);
+
+fn is_untyped(expression: &Expression) -> bool {
+    match expression {
+        Expression::LiteralExpression(..) => true,
+        Expression::BinOpExpression(bin_op) => {
+            is_untyped(&bin_op.left) && is_untyped(&bin_op.right)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ast::build::*;
+    use lexer::Lexer;
+    use parser::Parser;
+
+    fn errors(source: &str) -> Vec<String> {
+        let parser = Parser::new(Lexer::tokenise_source(source));
+        assert_eq!(parser.get_errors(), &Vec::<String>::new());
+        let checker = TypeCheck::new(parser.get_program().unwrap());
+        assert_eq!(checker.symbols().get_errors(), &Vec::<String>::new());
+        checker.get_errors().clone()
+    }
+
+    #[test]
+    fn well_typed_program() {
+        assert_eq!(
+            errors(
+                "fn square(a: i64) -> i64 { return a * a; }\n\
+                 fn main() -> i64 {\n\
+                 let a: i64 = square(3) + 1;\n\
+                 while a { a = a - 1; print_int(a); }\n\
+                 if square(a) { return 1; } else { return 0; }\n\
+                 }"
+            ),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn types_every_expression() {
+        let program = program([fn_decl("f").param("a", u8()).returns(u8()).body([ret(add(
+            var("a"),
+            mul(typed_lit(SimpleType::U8, 2), call("f", [var("a")])),
+        ))])]);
+        let checker = TypeCheck::new(&program);
+        assert_eq!(checker.get_errors(), &Vec::<String>::new());
+
+        let Declaration::FunctionDeclaration(f) = &program.declarations[0];
+        let Statement::ReturnStatement(Some(sum), _) = &f.body[0] else {
+            panic!("expected a return");
+        };
+        let Expression::BinOpExpression(sum_op) = sum else {
+            panic!("expected a sum");
+        };
+        let Expression::BinOpExpression(product) = sum_op.right.as_ref() else {
+            panic!("expected a product");
+        };
+        for expression in [sum, &sum_op.left, &product.left, &product.right] {
+            assert_eq!(checker.type_of(expression), Some(u8()));
+        }
+    }
+
+    #[test]
+    fn mismatched_declarations_assignments_and_returns() {
+        assert_eq!(
+            errors(
+                "fn big() -> i64 { return 1; }\n\
+                 fn f(a: u8) -> u16 {\n\
+                 let x: u8 = big();\n\
+                 a = big();\n\
+                 return a;\n\
+                 }"
+            ),
+            vec![
+                String::from("Mismatched types at 2:12, expected u8 but found i64"),
+                String::from("Mismatched types at 3:4, expected u8 but found i64"),
+                String::from("Mismatched types at 4:7, expected u16 but found u8"),
+            ]
+        );
+    }
+
+    #[test]
+    fn call_arguments() {
+        assert_eq!(
+            errors(
+                "fn g(a: u8, b: i32) -> void {}\n\
+                 fn f(a: u8, b: i64) -> void {\n\
+                 g(a, b);\n\
+                 g(a);\n\
+                 }"
+            ),
+            vec![
+                String::from("Mismatched types at 2:5, expected i32 but found i64"),
+                String::from("Function \"g\" takes 2 arguments but 1 were given at 3:0"),
+            ]
+        );
+    }
+
+    #[test]
+    fn operands_must_agree() {
+        assert_eq!(
+            errors("fn f(a: u8, b: i8) -> u8 { return a + b * 2; }"),
+            vec![String::from(
+                "Mismatched operands of \"+\" at 0:36, u8 and i8"
+            )]
+        );
+    }
+
+    #[test]
+    fn void_is_not_a_value() {
+        assert_eq!(
+            errors(
+                "fn v() -> void {}\n\
+                 fn f() -> i64 {\n\
+                 v();\n\
+                 let a: i64 = v();\n\
+                 let b: void = 1;\n\
+                 if v() {}\n\
+                 return 1 + v();\n\
+                 }\n\
+                 fn main() -> u64 { let b: void = 1; return b; }\n\
+                 fn g(a: void) -> u64 { return a; }"
+            ),
+            vec![
+                String::from("Function \"v\" returns void and cannot be used as a value at 3:13"),
+                String::from("Variable \"b\" at 4:4 cannot have type void"),
+                String::from("Function \"v\" returns void and cannot be used as a value at 5:3"),
+                String::from("Function \"v\" returns void and cannot be used as a value at 6:11"),
+                String::from("Variable \"b\" at 8:23 cannot have type void"),
+                String::from("Variable \"b\" has type void and cannot be used as a value at 8:43"),
+                String::from("Parameter \"a\" at 9:5 cannot have type void"),
+                String::from("Variable \"a\" has type void and cannot be used as a value at 9:30"),
+            ]
+        );
+    }
+
+    // With node ids and spans reset, to compare its statements to hand built ones
+    fn inferred(source: &str) -> Program {
+        let parser = Parser::new(Lexer::tokenise_source(source));
+        let mut program = parser.get_program().unwrap().clone();
+        let checker = TypeCheck::new(&program);
+        assert_eq!(checker.get_errors(), &Vec::<String>::new());
+        checker.rewrite_literals(&mut program);
+        program.without_meta()
+    }
+
+    fn body(program: &Program, function: usize) -> &Vec<Statement> {
+        let Declaration::FunctionDeclaration(function) = &program.declarations[function];
+        &function.body
+    }
+
+    #[test]
+    fn literals_take_the_type_of_their_context() {
+        let program = inferred(
+            "fn g(a: u16, b: i8) -> u32 { return 1 + 2 * 3; }\n\
+             fn f(a: u8) -> void {\n\
+             let x: u8 = 5;\n\
+             a = 7 - a;\n\
+             g(1, 2);\n\
+             while 1 + 2 {}\n\
+             }",
+        );
+        assert_eq!(
+            body(&program, 0),
+            &vec![ret(add(
+                typed_lit(SimpleType::U32, 1),
+                mul(typed_lit(SimpleType::U32, 2), typed_lit(SimpleType::U32, 3))
+            ))]
+        );
+        assert_eq!(
+            body(&program, 1),
+            &vec![
+                decl("x", u8(), typed_lit(SimpleType::U8, 5)),
+                assign("a", sub(typed_lit(SimpleType::U8, 7), var("a"))),
+                expr(call(
+                    "g",
+                    [typed_lit(SimpleType::U16, 1), typed_lit(SimpleType::I8, 2)]
+                )),
+                while_loop(
+                    add(typed_lit(SimpleType::I64, 1), typed_lit(SimpleType::I64, 2)),
+                    []
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn inference_does_not_hide_mismatches() {
+        // The literal follows a, so the sum is a u8 where an i32 is expected
+        assert_eq!(
+            errors("fn f(a: u8) -> i32 { let b: i32 = 1 + a; return b; }"),
+            vec![String::from(
+                "Mismatched types at 0:36, expected i32 but found u8"
+            )]
+        );
+    }
+
+    #[test]
+    fn literals_must_fit_their_type() {
+        assert_eq!(
+            errors(
+                "fn f(a: u8) -> i8 {\n\
+                 let x: u8 = 300;\n\
+                 let y: i8 = 200;\n\
+                 let z: u64 = 18446744073709551616;\n\
+                 a = a + 256;\n\
+                 return 127;\n\
+                 }"
+            ),
+            vec![
+                String::from("Literal 300 at 1:12 is out of range for u8, valid range is 0..=255"),
+                String::from(
+                    "Literal 200 at 2:12 is out of range for i8, valid range is -128..=127"
+                ),
+                String::from(
+                    "Literal 18446744073709551616 at 3:13 is out of range for u64, valid range is 0..=18446744073709551615"
+                ),
+                String::from("Literal 256 at 4:8 is out of range for u8, valid range is 0..=255"),
+            ]
+        );
+    }
+
+    #[test]
+    fn folded_constants_must_fit_their_type() {
+        assert_eq!(
+            errors(
+                "fn f() -> void {\n\
+                 let a: u8 = 255 + 1;\n\
+                 let b: u8 = 1 - 2 + 5;\n\
+                 let c: i8 = 100 * 2 / 4;\n\
+                 let d: i16 = 300 * 100 - 30000;\n\
+                 let e: u8 = 255 + 0 * 7;\n\
+                 let f: u64 = 18446744073709551615 * 18446744073709551615;\n\
+                 }"
+            ),
+            vec![
+                String::from(
+                    "Constant expression at 1:16 evaluates to 256, out of range for u8, valid range is 0..=255"
+                ),
+                String::from(
+                    "Constant expression at 2:14 evaluates to -1, out of range for u8, valid range is 0..=255"
+                ),
+                String::from(
+                    "Constant expression at 3:16 evaluates to 200, out of range for i8, valid range is -128..=127"
+                ),
+                String::from(
+                    "Constant expression at 6:34 overflows, out of range for u64, valid range is 0..=18446744073709551615"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn casts_convert_between_integer_types() {
+        assert_eq!(
+            errors(
+                "fn v() -> void {}\n\
+                 fn f(a: u8, b: i64) -> u16 {\n\
+                 let c: i8 = b as i8 + a as i8;\n\
+                 let d: u8 = 300 as u8;\n\
+                 let e: i64 = v() as i64;\n\
+                 let g: u8 = a as void;\n\
+                 return a as u16 * 2 + c;\n\
+                 }"
+            ),
+            vec![
+                String::from("Function \"v\" returns void and cannot be used as a value at 4:13"),
+                String::from("Cannot cast to void at 5:14"),
+                String::from("Mismatched operands of \"+\" at 6:20, u16 and i8"),
+            ]
+        );
+
+        // The literal in a cast is not constrained by the target type
+        let program = inferred("fn f() -> u8 { return 300 as u8; }");
+        assert_eq!(
+            body(&program, 0),
+            &vec![ret(cast(typed_lit(SimpleType::I64, 300), u8()))]
+        );
+    }
+
+    #[test]
+    fn builtins_are_typed() {
+        assert_eq!(
+            errors(
+                "fn main() -> void {\n\
+                 let a: u8 = read_int();\n\
+                 print_uint(read_int());\n\
+                 print_char(65);\n\
+                 print_char(256);\n\
+                 exit(0, 1);\n\
+                 }"
+            ),
+            vec![
+                String::from("Mismatched types at 1:12, expected u8 but found i64"),
+                String::from("Mismatched types at 2:11, expected u64 but found i64"),
+                String::from("Literal 256 at 4:11 is out of range for u8, valid range is 0..=255"),
+                String::from("Function \"exit\" takes 1 arguments but 2 were given at 5:0"),
+            ]
+        );
+    }
+}